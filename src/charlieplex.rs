@@ -0,0 +1,303 @@
+//! Charlieplexed LED driver
+//!
+//! With `N` pins, up to `N * (N - 1)` LEDs can be connected, one between every ordered pair of
+//! pins. An LED lights up when its anode is driven high and its cathode low, while all other
+//! pins float.
+//!
+//! Only one LED is on at a time, so `tick` has to be called periodically, e.g. from a
+//! `timers::Timer` interrupt, to cycle through them. The brightness of each LED is set by the
+//! number of cycles it stays on.
+
+use embedded_hal::digital::v2::OutputPin;
+
+use crate::gpio::FlexPin;
+
+/// Pin which can either float or drive a level
+pub trait Tristate {
+    type Error;
+
+    /// Stops driving the pin
+    fn float(&mut self) -> Result<(), Self::Error>;
+
+    /// Drives the pin high (`true`) or low (`false`)
+    fn drive(&mut self, high: bool) -> Result<(), Self::Error>;
+}
+
+impl Tristate for FlexPin {
+    type Error = ();
+
+    fn float(&mut self) -> Result<(), ()> {
+        self.set_as_input();
+        Ok(())
+    }
+
+    fn drive(&mut self, high: bool) -> Result<(), ()> {
+        // Set the level first, so the pin doesn't glitch
+        if high {
+            self.set_high()?;
+        } else {
+            self.set_low()?;
+        }
+        self.set_as_output();
+        Ok(())
+    }
+}
+
+/// Returns the anode and cathode pin of the LED `index` with `n` pins
+///
+/// The LEDs are numbered by anode first, skipping the pin itself as cathode
+///
+/// `n` has to be at least 2, which `Charlieplex::new` makes sure of
+fn led_pins(index: usize, n: usize) -> (usize, usize) {
+    let anode = index / (n - 1);
+    let cathode = index % (n - 1);
+    if cathode >= anode {
+        (anode, cathode + 1)
+    } else {
+        (anode, cathode)
+    }
+}
+
+/// Charlieplexed LEDs on `N` pins
+pub struct Charlieplex<P, const N: usize> {
+    pins: [P; N],
+    /// Brightness indexed by anode and cathode
+    frame: [[u8; N]; N],
+    levels: u8,
+    led: usize,
+    phase: u8,
+}
+
+impl<P, const N: usize> Charlieplex<P, N>
+where
+    P: Tristate,
+{
+    /// Number of LEDs
+    pub const LEDS: usize = N * (N - 1);
+
+    /// Creates a driver with `levels` brightness levels, 1 just switches the LEDs on and off
+    pub fn new(mut pins: [P; N], levels: u8) -> Result<Self, P::Error> {
+        assert!(N >= 2 && levels != 0);
+        for pin in pins.iter_mut() {
+            pin.float()?;
+        }
+        Ok(Charlieplex {
+            pins,
+            frame: [[0; N]; N],
+            levels,
+            led: 0,
+            phase: 0,
+        })
+    }
+
+    /// Sets the brightness of the LED `index`, from 0 (off) to `levels` (fully on)
+    pub fn set(&mut self, index: usize, brightness: u8) {
+        let (anode, cathode) = led_pins(index, N);
+        self.frame[anode][cathode] = brightness;
+    }
+
+    /// Switches the LED `index` fully on or off
+    pub fn set_on(&mut self, index: usize, on: bool) {
+        let brightness = if on { self.levels } else { 0 };
+        self.set(index, brightness);
+    }
+
+    /// Returns the brightness of the LED `index`
+    pub fn get(&self, index: usize) -> u8 {
+        let (anode, cathode) = led_pins(index, N);
+        self.frame[anode][cathode]
+    }
+
+    /// Switches all LEDs off
+    pub fn clear(&mut self) {
+        self.frame = [[0; N]; N];
+    }
+
+    /// Switches off the current LED and lights the next one, if its brightness allows it
+    ///
+    /// All LEDs are visited once per brightness level, so a full refresh takes
+    /// `LEDS * levels` ticks
+    pub fn tick(&mut self) -> Result<(), P::Error> {
+        let (anode, cathode) = led_pins(self.led, N);
+        self.pins[anode].float()?;
+        self.pins[cathode].float()?;
+
+        self.led += 1;
+        if self.led == Self::LEDS {
+            self.led = 0;
+            self.phase = (self.phase + 1) % self.levels;
+        }
+
+        let (anode, cathode) = led_pins(self.led, N);
+        if self.frame[anode][cathode] > self.phase {
+            self.pins[cathode].drive(false)?;
+            self.pins[anode].drive(true)?;
+        }
+        Ok(())
+    }
+
+    /// Floats all pins and releases them
+    pub fn release(mut self) -> Result<[P; N], P::Error> {
+        for pin in self.pins.iter_mut() {
+            pin.float()?;
+        }
+        Ok(self.pins)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::RefCell;
+
+    #[derive(Clone, Copy, PartialEq, Debug)]
+    enum State {
+        Float,
+        High,
+        Low,
+    }
+
+    /// Pin which records every change into a log shared by all pins
+    struct Recorder<'a> {
+        log: &'a RefCell<Vec<(usize, State)>>,
+        i: usize,
+    }
+
+    impl Tristate for Recorder<'_> {
+        type Error = ();
+
+        fn float(&mut self) -> Result<(), ()> {
+            self.log.borrow_mut().push((self.i, State::Float));
+            Ok(())
+        }
+
+        fn drive(&mut self, high: bool) -> Result<(), ()> {
+            let state = if high { State::High } else { State::Low };
+            self.log.borrow_mut().push((self.i, state));
+            Ok(())
+        }
+    }
+
+    fn charlieplex<const N: usize>(
+        log: &RefCell<Vec<(usize, State)>>,
+        levels: u8,
+    ) -> Charlieplex<Recorder<'_>, N> {
+        let mut i = 0;
+        let pins = [(); N].map(|_| {
+            i += 1;
+            Recorder { log, i: i - 1 }
+        });
+        let leds = Charlieplex::new(pins, levels).unwrap();
+        log.borrow_mut().clear();
+        leds
+    }
+
+    #[test]
+    fn led_pins_cover_every_ordered_pair() {
+        let pins: Vec<_> = (0..6).map(|index| led_pins(index, 3)).collect();
+        assert_eq!(pins, [(0, 1), (0, 2), (1, 0), (1, 2), (2, 0), (2, 1)]);
+
+        let mut pins: Vec<_> = (0..12).map(|index| led_pins(index, 4)).collect();
+        pins.sort();
+        pins.dedup();
+        assert_eq!(pins.len(), 12);
+        assert!(pins
+            .iter()
+            .all(|&(anode, cathode)| anode != cathode && anode < 4 && cathode < 4));
+    }
+
+    #[test]
+    fn new_floats_all_pins() {
+        let log = RefCell::new(Vec::new());
+        let pins = [0, 1, 2].map(|i| Recorder { log: &log, i });
+        Charlieplex::new(pins, 1).unwrap();
+        assert_eq!(
+            *log.borrow(),
+            [(0, State::Float), (1, State::Float), (2, State::Float)]
+        );
+    }
+
+    #[test]
+    fn tick_lights_one_led_after_another() {
+        let log = RefCell::new(Vec::new());
+        let mut leds = charlieplex::<3>(&log, 1);
+        for index in 0..Charlieplex::<Recorder, 3>::LEDS {
+            leds.set_on(index, true);
+        }
+
+        // Each tick floats the previous LED, then drives the cathode before the anode
+        for n in 1..=6 {
+            let (previous_anode, previous_cathode) = led_pins(n - 1, 3);
+            let (anode, cathode) = led_pins(n % 6, 3);
+            leds.tick().unwrap();
+            assert_eq!(
+                log.replace(Vec::new()),
+                [
+                    (previous_anode, State::Float),
+                    (previous_cathode, State::Float),
+                    (cathode, State::Low),
+                    (anode, State::High),
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn tick_skips_dark_leds() {
+        let log = RefCell::new(Vec::new());
+        let mut leds = charlieplex::<3>(&log, 1);
+        leds.set_on(3, true);
+        assert_eq!(leds.get(3), 1);
+
+        leds.tick().unwrap();
+        leds.tick().unwrap();
+        assert!(log
+            .replace(Vec::new())
+            .iter()
+            .all(|&(_, state)| state == State::Float));
+        leds.tick().unwrap();
+        assert_eq!(
+            log.replace(Vec::new())[2..],
+            [(2, State::Low), (1, State::High)]
+        );
+
+        leds.clear();
+        for _ in 0..6 {
+            leds.tick().unwrap();
+        }
+        assert!(log.borrow().iter().all(|&(_, state)| state == State::Float));
+    }
+
+    #[test]
+    fn brightness_sets_the_share_of_cycles() {
+        let log = RefCell::new(Vec::new());
+        let mut leds = charlieplex::<2>(&log, 2);
+        leds.set(0, 1);
+        leds.set(1, 2);
+
+        // LED 0 is pin 0 to 1, LED 1 is pin 1 to 0. The brightness levels are cycled through
+        // after all LEDs have been visited once.
+        let lit = [true, false, true, true];
+        for &lit in &lit {
+            leds.tick().unwrap();
+            let drives = log
+                .replace(Vec::new())
+                .iter()
+                .filter(|&&(_, state)| state != State::Float)
+                .count();
+            assert_eq!(drives, if lit { 2 } else { 0 });
+        }
+    }
+
+    #[test]
+    fn release_floats_all_pins() {
+        let log = RefCell::new(Vec::new());
+        let mut leds = charlieplex::<2>(&log, 1);
+        leds.set_on(1, true);
+        leds.tick().unwrap();
+        log.borrow_mut().clear();
+
+        leds.release().unwrap();
+        assert_eq!(*log.borrow(), [(0, State::Float), (1, State::Float)]);
+    }
+}
@@ -9,11 +9,14 @@
 use cast::{u16, u32};
 use core::ops::Deref;
 
-use crate::syscon::{ClockEnable, Syscon};
+use crate::syscon::{ClockEnable, ClockError, Clocks, Syscon};
+use crate::time::Hertz;
 use crate::timers::TimerRegisterBlock;
 use embedded_hal::blocking::delay::{DelayMs, DelayUs};
 
-#[derive(Clone, Copy)]
+/// Delay provider on a timer
+///
+/// This isn't `Clone`, so `refresh` updates the only instance that uses the timer
 pub struct Delay {
     pub(crate) scale: u32,
     pub(crate) timer: *const TimerRegisterBlock,
@@ -30,19 +33,58 @@ impl Delay {
     where
         TIMER: Deref<Target = TimerRegisterBlock> + ClockEnable,
     {
+        Self::try_new(timer, syscon).map_err(|(e, _)| e).unwrap()
+    }
+
+    /// Fails if the timer clock is slower than 1 MHz
+    ///
+    /// The timer is returned untouched on error. For timer clocks which aren't a whole number of
+    /// MHz the delays are rounded up.
+    pub fn try_new<TIMER>(timer: TIMER, syscon: &mut Syscon) -> Result<Delay, (ClockError, TIMER)>
+    where
+        TIMER: Deref<Target = TimerRegisterBlock> + ClockEnable,
+    {
+        let scale = match ticks_per_us(syscon.clocks.timsclk()) {
+            Ok(scale) => scale,
+            Err(e) => return Err((e, timer)),
+        };
         TIMER::enable(syscon);
-        assert!(syscon.clocks.timsclk().0 >= 1_000_000);
-        let scale = syscon.clocks.timsclk().0 / 1_000_000;
 
         // Count to the highest possible value
         unsafe { timer.tarval.write(|w| w.bits(0xFFFFFFFF)) };
         // Start counting
         timer.ctrl.write(|w| w.ena().set_bit());
-        Delay {
+        Ok(Delay {
             timer: &(*timer),
             scale,
             countdown: None,
+        })
+    }
+
+    /// Recalculates the delays after the clocks were changed with `Syscon::reconfigure`
+    ///
+    /// Fails if the new timer clock is slower than 1 MHz, in which case nothing changes. A
+    /// running countdown keeps its period, but the current one may be cut short or extended.
+    pub fn refresh(&mut self, clocks: Clocks) -> Result<(), ClockError> {
+        let scale = ticks_per_us(clocks.timsclk())?;
+        if let Some((ticks, start_count)) = self.countdown {
+            let ticks = u64::from(ticks) * u64::from(scale) / u64::from(self.scale);
+            self.countdown = Some((ticks as u32, start_count));
         }
+        self.scale = scale;
+        Ok(())
+    }
+}
+
+/// Returns the number of timer ticks in a microsecond
+///
+/// This is rounded up, so the delays are never shorter than requested. A microsecond has to be
+/// at least one tick, otherwise the delays would be too short even then.
+fn ticks_per_us(timsclk: Hertz) -> Result<u32, ClockError> {
+    if timsclk.0 < 1_000_000 {
+        Err(ClockError::PeriodOutOfRange)
+    } else {
+        Ok(timsclk.0.div_ceil(1_000_000))
     }
 }
 
@@ -98,3 +140,27 @@ impl DelayUs<u8> for Delay {
         self.delay_us(u32(us))
     }
 }
+
+#[cfg(feature = "eh1")]
+impl embedded_hal_1::delay::DelayNs for Delay {
+    fn delay_ns(&mut self, ns: u32) {
+        // Round up, so the delay is never too short
+        let ticks = (u64::from(ns) * u64::from(self.scale)).div_ceil(1_000);
+        let ticks = ticks as u32;
+
+        let start_count = unsafe { (*(self.timer)).curval.read().bits() };
+
+        while (unsafe {
+            (*(self.timer))
+                .curval
+                .read()
+                .bits()
+                .wrapping_sub(start_count)
+        }) < ticks
+        {}
+    }
+
+    fn delay_ms(&mut self, ms: u32) {
+        DelayMs::delay_ms(self, ms)
+    }
+}
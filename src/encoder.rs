@@ -0,0 +1,350 @@
+//! Quadrature decoder for rotary encoders connected to two GPIO inputs
+//!
+//! The encoder can either be polled by calling `update` from a periodic tick, which has to be
+//! faster than the fastest expected transition, or be driven by pin-change interrupts with
+//! `listen` and `on_interrupt`.
+
+use cortex_m::interrupt::CriticalSection;
+use embedded_hal::digital::v2::InputPin;
+
+use crate::gpio::{InterruptPin, Trigger};
+
+/// Result of a single transition of the A/B signals
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Step {
+    /// No change
+    None,
+    /// One step forward, in the sequence 00, 01, 11, 10 (A is the high bit)
+    Forward,
+    /// One step backward
+    Backward,
+    /// Both signals changed at once, so the direction is unknown
+    Invalid,
+}
+
+/// Transition table indexed by `previous << 2 | current`
+const STEPS: [Step; 16] = [
+    // From 00
+    Step::None,
+    Step::Forward,
+    Step::Backward,
+    Step::Invalid,
+    // From 01
+    Step::Backward,
+    Step::None,
+    Step::Invalid,
+    Step::Forward,
+    // From 10
+    Step::Forward,
+    Step::Invalid,
+    Step::None,
+    Step::Backward,
+    // From 11
+    Step::Invalid,
+    Step::Backward,
+    Step::Forward,
+    Step::None,
+];
+
+/// Quadrature decoder state machine
+///
+/// This only tracks the signals, so it can be used with any source of samples
+#[derive(Clone, Copy)]
+pub struct Decoder {
+    state: u8,
+    steps: i32,
+    errors: u32,
+}
+
+impl Decoder {
+    /// Creates a decoder starting with the given levels of A and B
+    pub fn new(a: bool, b: bool) -> Self {
+        Decoder {
+            state: Self::state(a, b),
+            steps: 0,
+            errors: 0,
+        }
+    }
+
+    fn state(a: bool, b: bool) -> u8 {
+        ((a as u8) << 1) | b as u8
+    }
+
+    /// Feeds the current levels of A and B into the decoder
+    pub fn update(&mut self, a: bool, b: bool) -> Step {
+        let state = Self::state(a, b);
+        let step = STEPS[usize::from((self.state << 2) | state)];
+        match step {
+            Step::Forward => self.steps = self.steps.wrapping_add(1),
+            Step::Backward => self.steps = self.steps.wrapping_sub(1),
+            Step::Invalid => self.errors = self.errors.wrapping_add(1),
+            Step::None => (),
+        }
+        self.state = state;
+        step
+    }
+
+    /// Returns the number of steps since creation or the last reset
+    pub fn steps(&self) -> i32 {
+        self.steps
+    }
+
+    /// Returns the number of invalid transitions
+    pub fn errors(&self) -> u32 {
+        self.errors
+    }
+
+    /// Resets the position and error count
+    pub fn reset(&mut self) {
+        self.steps = 0;
+        self.errors = 0;
+    }
+}
+
+/// Rotary encoder on two input pins
+pub struct Encoder<A, B> {
+    a: A,
+    b: B,
+    decoder: Decoder,
+    steps_per_detent: u8,
+    /// Position in detents
+    position: i32,
+    /// Steps at which the position was last reached
+    anchor: i32,
+}
+
+impl<A, B, E> Encoder<A, B>
+where
+    A: InputPin<Error = E>,
+    B: InputPin<Error = E>,
+{
+    /// Creates an encoder, which reports one position per `steps_per_detent` steps
+    ///
+    /// Most mechanical encoders have 4 steps per detent
+    pub fn new(a: A, b: B, steps_per_detent: u8) -> Result<Self, E> {
+        assert!(steps_per_detent != 0);
+        let decoder = Decoder::new(a.is_high()?, b.is_high()?);
+        Ok(Encoder {
+            a,
+            b,
+            decoder,
+            steps_per_detent,
+            position: 0,
+            anchor: 0,
+        })
+    }
+
+    /// Samples the pins and updates the position
+    pub fn update(&mut self) -> Result<Step, E> {
+        let a = self.a.is_high()?;
+        let b = self.b.is_high()?;
+        let step = self.decoder.update(a, b);
+        // Only move by whole detents away from the last position, so a contact bouncing at
+        // rest doesn't make the position flicker
+        let steps_per_detent = i32::from(self.steps_per_detent);
+        let detents = self.decoder.steps().wrapping_sub(self.anchor) / steps_per_detent;
+        if detents != 0 {
+            self.position = self.position.wrapping_add(detents);
+            self.anchor = self
+                .anchor
+                .wrapping_add(detents.wrapping_mul(steps_per_detent));
+        }
+        Ok(step)
+    }
+
+    /// Returns the position in detents
+    ///
+    /// The position changes once the encoder has moved a whole detent away from the last
+    /// position, in either direction
+    pub fn position(&self) -> i32 {
+        self.position
+    }
+
+    /// Returns the position in steps
+    pub fn steps(&self) -> i32 {
+        self.decoder.steps()
+    }
+
+    /// Returns the number of invalid transitions, which means that samples were missed
+    pub fn errors(&self) -> u32 {
+        self.decoder.errors()
+    }
+
+    /// Resets the position and error count
+    pub fn reset(&mut self) {
+        self.decoder.reset();
+        self.position = 0;
+        self.anchor = 0;
+    }
+
+    /// Releases the pins
+    pub fn release(self) -> (A, B) {
+        (self.a, self.b)
+    }
+}
+
+impl<A, B, E> Encoder<A, B>
+where
+    A: InputPin<Error = E> + InterruptPin,
+    B: InputPin<Error = E> + InterruptPin,
+{
+    /// Enables the pin-change interrupts of both pins
+    ///
+    /// `on_interrupt` then has to be called from the GPIOA interrupt handler
+    pub fn listen(&mut self, cs: &CriticalSection) {
+        self.a.listen(Trigger::RisingFalling, cs);
+        self.b.listen(Trigger::RisingFalling, cs);
+    }
+
+    /// Disables the pin-change interrupts of both pins
+    pub fn unlisten(&mut self, cs: &CriticalSection) {
+        self.a.unlisten(cs);
+        self.b.unlisten(cs);
+    }
+
+    /// Handles a pending interrupt of either pin
+    ///
+    /// Returns `Step::None` if none of the pins caused the interrupt
+    pub fn on_interrupt(&mut self) -> Result<Step, E> {
+        let a = self.a.is_interrupt_pending();
+        let b = self.b.is_interrupt_pending();
+        if !a && !b {
+            return Ok(Step::None);
+        }
+        if a {
+            self.a.clear_interrupt_pending_bit();
+        }
+        if b {
+            self.b.clear_interrupt_pending_bit();
+        }
+        self.update()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::Cell;
+
+    /// States in forward order, A is the high bit
+    const GRAY: [u8; 4] = [0b00, 0b01, 0b11, 0b10];
+
+    fn levels(state: u8) -> (bool, bool) {
+        (state & 0b10 != 0, state & 0b01 != 0)
+    }
+
+    #[test]
+    fn steps_table_follows_gray_code() {
+        for (n, &from) in GRAY.iter().enumerate() {
+            let next = GRAY[(n + 1) % 4];
+            let previous = GRAY[(n + 3) % 4];
+            let opposite = GRAY[(n + 2) % 4];
+            let step = |to: u8| STEPS[usize::from((from << 2) | to)];
+            assert_eq!(step(from), Step::None);
+            assert_eq!(step(next), Step::Forward);
+            assert_eq!(step(previous), Step::Backward);
+            assert_eq!(step(opposite), Step::Invalid);
+        }
+    }
+
+    #[test]
+    fn decoder_counts_steps_and_errors() {
+        let mut decoder = Decoder::new(false, false);
+        for n in 1..=8 {
+            let (a, b) = levels(GRAY[n % 4]);
+            assert_eq!(decoder.update(a, b), Step::Forward);
+        }
+        assert_eq!(decoder.steps(), 8);
+
+        for n in (1..4).rev() {
+            let (a, b) = levels(GRAY[n]);
+            assert_eq!(decoder.update(a, b), Step::Backward);
+        }
+        assert_eq!(decoder.steps(), 5);
+        assert_eq!(decoder.errors(), 0);
+
+        // From 01 to 10, both signals changed
+        assert_eq!(decoder.update(true, false), Step::Invalid);
+        assert_eq!(decoder.update(true, false), Step::None);
+        assert_eq!(decoder.steps(), 5);
+        assert_eq!(decoder.errors(), 1);
+
+        decoder.reset();
+        assert_eq!(decoder.steps(), 0);
+        assert_eq!(decoder.errors(), 0);
+    }
+
+    /// Input reading a level shared with the test
+    struct Level<'a>(&'a Cell<bool>);
+
+    impl InputPin for Level<'_> {
+        type Error = ();
+
+        fn is_high(&self) -> Result<bool, ()> {
+            Ok(self.0.get())
+        }
+
+        fn is_low(&self) -> Result<bool, ()> {
+            Ok(!self.0.get())
+        }
+    }
+
+    /// Turns the encoder by `steps` and samples every step
+    fn turn(encoder: &mut Encoder<Level, Level>, a: &Cell<bool>, b: &Cell<bool>, steps: i32) {
+        for _ in 0..steps.abs() {
+            let state = (u8::from(a.get()) << 1) | u8::from(b.get());
+            let n = GRAY.iter().position(|&s| s == state).unwrap();
+            let next = if steps > 0 { n + 1 } else { n + 3 };
+            let (next_a, next_b) = levels(GRAY[next % 4]);
+            a.set(next_a);
+            b.set(next_b);
+            encoder.update().unwrap();
+        }
+    }
+
+    #[test]
+    fn jitter_at_a_detent_keeps_the_position() {
+        let (a, b) = (Cell::new(false), Cell::new(false));
+        let mut encoder = Encoder::new(Level(&a), Level(&b), 4).unwrap();
+
+        turn(&mut encoder, &a, &b, -1);
+        assert_eq!(encoder.position(), 0);
+        turn(&mut encoder, &a, &b, 1);
+        turn(&mut encoder, &a, &b, 3);
+        assert_eq!(encoder.position(), 0);
+        turn(&mut encoder, &a, &b, 1);
+        assert_eq!(encoder.position(), 1);
+
+        // Bouncing back by a step at the new detent
+        turn(&mut encoder, &a, &b, -1);
+        assert_eq!(encoder.position(), 1);
+        turn(&mut encoder, &a, &b, 1);
+        assert_eq!(encoder.position(), 1);
+        assert_eq!(encoder.steps(), 4);
+    }
+
+    #[test]
+    fn position_is_symmetric() {
+        let (a, b) = (Cell::new(false), Cell::new(false));
+        let mut encoder = Encoder::new(Level(&a), Level(&b), 4).unwrap();
+
+        turn(&mut encoder, &a, &b, -3);
+        assert_eq!(encoder.position(), 0);
+        turn(&mut encoder, &a, &b, -1);
+        assert_eq!(encoder.position(), -1);
+        turn(&mut encoder, &a, &b, -8);
+        assert_eq!(encoder.position(), -3);
+        turn(&mut encoder, &a, &b, 12);
+        assert_eq!(encoder.position(), 0);
+        assert_eq!(encoder.steps(), 0);
+        assert_eq!(encoder.errors(), 0);
+
+        turn(&mut encoder, &a, &b, 6);
+        encoder.reset();
+        assert_eq!(encoder.position(), 0);
+        turn(&mut encoder, &a, &b, 2);
+        assert_eq!(encoder.position(), 0);
+        turn(&mut encoder, &a, &b, 2);
+        assert_eq!(encoder.position(), 1);
+    }
+}
@@ -1,8 +1,29 @@
 //! General Purpose Input / Output
 
+use core::cell::Cell;
+#[cfg(feature = "eh1")]
+use core::convert::Infallible;
 use core::marker::PhantomData;
 
-// TODO Implement marker for af with PushPull or OpenDrain
+#[cfg(not(test))]
+use cortex_m::interrupt::free;
+use cortex_m::interrupt::{CriticalSection, Mutex};
+
+use crate::swm050::{GPIOA, PORT};
+
+mod debounce;
+
+/// Stand-in for `interrupt::free` in the host tests, which have no interrupts to disable
+#[cfg(test)]
+fn free<F, R>(f: F) -> R
+where
+    F: FnOnce(&CriticalSection) -> R,
+{
+    f(unsafe { &CriticalSection::new() })
+}
+
+pub use self::debounce::{DebounceConfig, Debounced, Event};
+
 /// Extension trait to split a GPIO peripheral in independent pins and registers
 pub trait GpioExt {
     /// The parts to split the GPIO into
@@ -13,22 +34,230 @@ pub trait GpioExt {
     fn split(self) -> Self::Parts;
 }
 
-trait GpioRegExt {
-    fn is_low(&self, pos: u8) -> bool;
-    fn is_set_low(&self, pos: u8) -> bool;
-    fn set_high(&self, pos: u8);
-    fn set_low(&self, pos: u8);
+/// Registers of a GPIO port
+#[derive(Clone, Copy, PartialEq)]
+enum GpioRegister {
+    /// Data
+    Dat,
+    /// Direction, set bits are outputs
+    Dir,
+    /// Interrupt enable
+    IntEn,
+    /// Interrupt mask, set bits are masked
+    IntMask,
+    /// Interrupt type, set bits are edge triggered
+    IntType,
+    /// Interrupt polarity, set bits trigger on a rising edge or high level
+    IntPol,
+    /// Interrupt status, read only
+    IntStat,
+    /// Interrupt clear, write only
+    IntClr,
 }
 
+/// Raw access to the registers of a GPIO port
+///
+/// All the pin logic in `GpioRegExt` is built on top of this, so it doesn't depend on the
+/// actual peripheral and can also be backed by plain memory
+trait GpioRegisters {
+    fn read(&self, reg: GpioRegister) -> u32;
+    fn write(&self, reg: GpioRegister, bits: u32);
+
+    /// Sets (`true`) or clears (`false`) the bits of `mask` in `reg`
+    ///
+    /// There are no set and clear registers, so an interrupt changing other bits of `reg`
+    /// between the read and the write would be undone. This only takes a few cycles, so it's
+    /// always done in a critical section.
+    fn set_bits(&self, reg: GpioRegister, mask: u32, set: bool) {
+        free(|_| {
+            let bits = self.read(reg);
+            self.write(reg, if set { bits | mask } else { bits & !mask });
+        });
+    }
+}
+
+trait GpioRegExt: GpioRegisters {
+    fn is_low(&self, pos: u8) -> bool {
+        self.read(GpioRegister::Dat) & (1 << pos) == 0
+    }
+
+    fn is_set_low(&self, pos: u8) -> bool {
+        self.read(GpioRegister::Dat) & (1 << pos) == 0
+    }
+
+    fn set_high(&self, pos: u8) {
+        self.set_bits(GpioRegister::Dat, 1 << pos, true);
+    }
+
+    fn set_low(&self, pos: u8) {
+        self.set_bits(GpioRegister::Dat, 1 << pos, false);
+    }
+
+    fn toggle(&self, pos: u8) {
+        // There's no toggle register, so make sure that an interrupt can't modify `dat`
+        // in between
+        free(|_| {
+            let bits = self.read(GpioRegister::Dat);
+            self.write(GpioRegister::Dat, bits ^ (1 << pos));
+        });
+    }
+
+    fn set_input(&self, pos: u8) {
+        self.set_bits(GpioRegister::Dir, 1 << pos, false);
+    }
+
+    fn set_output(&self, pos: u8) {
+        self.set_bits(GpioRegister::Dir, 1 << pos, true);
+    }
+
+    fn is_output(&self, pos: u8) -> bool {
+        self.read(GpioRegister::Dir) & (1 << pos) != 0
+    }
+
+    fn release(&self, pos: u8) {
+        free(|_| {
+            self.set_input(pos);
+            // Other pins writing `dat` may have latched a high level, which would be
+            // driven on the next `drive_low`
+            self.set_low(pos);
+        });
+    }
+
+    fn drive_low(&self, pos: u8) {
+        free(|_| {
+            self.set_low(pos);
+            self.set_output(pos);
+        });
+    }
+
+    fn read_bits(&self, mask: u32) -> u32 {
+        self.read(GpioRegister::Dat) & mask
+    }
+
+    fn write_bits(&self, mask: u32, value: u32) {
+        free(|_| {
+            let bits = self.read(GpioRegister::Dat);
+            self.write(GpioRegister::Dat, (bits & !mask) | (value & mask));
+        });
+    }
+
+    fn listen(&self, pos: u8, trigger: Trigger, cs: &CriticalSection) {
+        let bit = 1 << pos;
+        let (edge, high) = match trigger {
+            Trigger::Rising => (true, true),
+            Trigger::Falling => (true, false),
+            // Wait for the edge leading away from the current level
+            Trigger::RisingFalling => (true, self.is_low(pos)),
+            Trigger::High => (false, true),
+            Trigger::Low => (false, false),
+        };
+        let both = BOTH_EDGES.borrow(cs);
+        if trigger == Trigger::RisingFalling {
+            both.set(both.get() | bit);
+        } else {
+            both.set(both.get() & !bit);
+        }
+        self.set_bits(GpioRegister::IntEn, bit, false);
+        self.set_bits(GpioRegister::IntType, bit, edge);
+        self.set_bits(GpioRegister::IntPol, bit, high);
+        self.write(GpioRegister::IntClr, bit);
+        self.set_bits(GpioRegister::IntMask, bit, false);
+        self.set_bits(GpioRegister::IntEn, bit, true);
+    }
+
+    fn unlisten(&self, pos: u8, cs: &CriticalSection) {
+        let bit = 1 << pos;
+        let both = BOTH_EDGES.borrow(cs);
+        both.set(both.get() & !bit);
+        self.set_bits(GpioRegister::IntEn, bit, false);
+        self.write(GpioRegister::IntClr, bit);
+    }
+
+    fn is_interrupt_pending(&self, pos: u8) -> bool {
+        self.read(GpioRegister::IntStat) & (1 << pos) != 0
+    }
+
+    fn clear_interrupt_pending_bit(&self, pos: u8) {
+        let bit = 1 << pos;
+        self.write(GpioRegister::IntClr, bit);
+        free(|cs| {
+            if BOTH_EDGES.borrow(cs).get() & bit != 0 {
+                // Re-arm for the opposite edge
+                self.set_bits(GpioRegister::IntPol, bit, self.is_low(pos));
+            }
+        });
+    }
+}
+
+impl<T> GpioRegExt for T where T: GpioRegisters {}
+
+/// Condition that triggers a pin-change interrupt
+#[derive(Clone, Copy, PartialEq)]
+pub enum Trigger {
+    /// Rising edge
+    Rising,
+    /// Falling edge
+    Falling,
+    /// Both rising and falling edges
+    ///
+    /// The hardware only detects one edge at a time, so this is emulated by
+    /// flipping the polarity every time the pending bit is cleared. An edge that
+    /// occurs before the interrupt has been cleared can be missed.
+    RisingFalling,
+    /// High level
+    High,
+    /// Low level
+    Low,
+}
+
+/// Pin-change interrupts of an input pin
+///
+/// To get the interrupt into the core, the port interrupt also needs to be
+/// unmasked in the NVIC, e.g. with `gpioa::unmask_interrupt`
+pub trait InterruptPin {
+    /// Enables the interrupt of this pin for the given trigger condition
+    fn listen(&mut self, trigger: Trigger, cs: &CriticalSection);
+
+    /// Disables the interrupt of this pin
+    fn unlisten(&mut self, cs: &CriticalSection);
+
+    /// Returns `true` if the interrupt of this pin is pending
+    fn is_interrupt_pending(&self) -> bool;
+
+    /// Clears the pending interrupt of this pin
+    ///
+    /// Level interrupts stay pending as long as the level is present
+    fn clear_interrupt_pending_bit(&mut self);
+}
+
+/// Pins which are set up to trigger on both edges
+// NOTE SWM050 only has GPIOA, so this doesn't need to be per port
+static BOTH_EDGES: Mutex<Cell<u32>> = Mutex::new(Cell::new(0));
+
+/// Alternate function 0 (type state)
 pub struct AF0;
+/// Alternate function 1 (type state)
 pub struct AF1;
+/// Alternate function 2 (type state)
 pub struct AF2;
+/// Alternate function 3 (type state)
 pub struct AF3;
+/// Alternate function 4 (type state)
 pub struct AF4;
+/// Alternate function 5 (type state)
 pub struct AF5;
+/// Alternate function 6 (type state)
 pub struct AF6;
+/// Alternate function 7 (type state)
 pub struct AF7;
 
+/// Alternate function mode (type state)
+///
+/// The pin is connected to a peripheral through `PORT.porta_sel`
+pub struct Alternate<AF> {
+    _mode: PhantomData<AF>,
+}
+
 /// Input mode (type state)
 pub struct Input<MODE> {
     _mode: PhantomData<MODE>,
@@ -38,51 +267,138 @@ pub struct Input<MODE> {
 pub struct Floating;
 
 /// Pulled up input (type state)
+///
+/// The pads only have pull-up resistors, so there is no pulled down counterpart
 pub struct PullUp;
 
-/// Output mode
-pub struct Output;
+/// Output mode (type state)
+pub struct Output<MODE> {
+    _mode: PhantomData<MODE>,
+}
+
+/// Push pull output (type state)
+pub struct PushPull;
 
-use embedded_hal::digital::v2::{toggleable, InputPin, OutputPin, StatefulOutputPin};
+/// Open drain output (type state)
+///
+/// The hardware has no open drain driver, so this is emulated. Setting the pin high releases
+/// the line by switching the pin to input, setting it low drives it low as output.
+pub struct OpenDrain;
+
+pub use embedded_hal::digital::v2::PinState;
+use embedded_hal::digital::v2::{
+    toggleable, InputPin, OutputPin, StatefulOutputPin, ToggleableOutputPin,
+};
 
 /// Fully erased pin
+///
+/// SWM050 only has GPIOA, so this is just the pin number
 pub struct Pin<MODE> {
     i: u8,
-    port: *const dyn GpioRegExt,
     _mode: PhantomData<MODE>,
 }
 
-// NOTE(unsafe) The only write acess is to BSRR, which is thread safe
-unsafe impl<MODE> Sync for Pin<MODE> {}
-// NOTE(unsafe) this only enables read access to the same pin from multiple
-// threads
-unsafe impl<MODE> Send for Pin<MODE> {}
+// An erased pin has to stay a single byte, so arrays of them are cheap
+const _: () = assert!(core::mem::size_of::<Pin<Output<PushPull>>>() == 1);
+
+impl<MODE> Pin<MODE> {
+    /// Creates the erased pin `PA_i`
+    ///
+    /// # Safety
+    ///
+    /// The pin must not be used anywhere else and it has to be configured in `MODE` already.
+    /// Usually `downgrade` should be used instead.
+    pub const unsafe fn new(i: u8) -> Self {
+        Pin {
+            i,
+            _mode: PhantomData,
+        }
+    }
+
+    /// Returns the pin number
+    pub const fn pin(&self) -> u8 {
+        self.i
+    }
 
-impl StatefulOutputPin for Pin<Output> {
+    /// Changes the type state only, the pin has to be configured already
+    fn into_mode<NEW>(self) -> Pin<NEW> {
+        Pin {
+            i: self.i,
+            _mode: PhantomData,
+        }
+    }
+}
+
+impl StatefulOutputPin for Pin<Output<PushPull>> {
     fn is_set_high(&self) -> Result<bool, ()> {
         self.is_set_low().map(|low| !low)
     }
 
     fn is_set_low(&self) -> Result<bool, ()> {
-        Ok(unsafe { (*self.port).is_set_low(self.i) })
+        Ok(unsafe { (*GPIOA::ptr()).is_set_low(self.i) })
     }
 }
 
-impl OutputPin for Pin<Output> {
+impl OutputPin for Pin<Output<PushPull>> {
     type Error = ();
 
     fn set_high(&mut self) -> Result<(), Self::Error> {
-        unsafe { (*self.port).set_high(self.i) };
+        unsafe { (*GPIOA::ptr()).set_high(self.i) };
         Ok(())
     }
 
     fn set_low(&mut self) -> Result<(), Self::Error> {
-        unsafe { (*self.port).set_low(self.i) }
+        unsafe { (*GPIOA::ptr()).set_low(self.i) }
+        Ok(())
+    }
+}
+
+impl ToggleableOutputPin for Pin<Output<PushPull>> {
+    type Error = ();
+
+    fn toggle(&mut self) -> Result<(), Self::Error> {
+        unsafe { (*GPIOA::ptr()).toggle(self.i) }
         Ok(())
     }
 }
 
-impl toggleable::Default for Pin<Output> {}
+impl StatefulOutputPin for Pin<Output<OpenDrain>> {
+    fn is_set_high(&self) -> Result<bool, ()> {
+        self.is_set_low().map(|low| !low)
+    }
+
+    fn is_set_low(&self) -> Result<bool, ()> {
+        Ok(unsafe { (*GPIOA::ptr()).is_output(self.i) })
+    }
+}
+
+impl OutputPin for Pin<Output<OpenDrain>> {
+    type Error = ();
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        unsafe { (*GPIOA::ptr()).release(self.i) };
+        Ok(())
+    }
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        unsafe { (*GPIOA::ptr()).drive_low(self.i) }
+        Ok(())
+    }
+}
+
+impl toggleable::Default for Pin<Output<OpenDrain>> {}
+
+impl InputPin for Pin<Output<OpenDrain>> {
+    type Error = ();
+
+    fn is_high(&self) -> Result<bool, ()> {
+        self.is_low().map(|low| !low)
+    }
+
+    fn is_low(&self) -> Result<bool, ()> {
+        Ok(unsafe { (*GPIOA::ptr()).is_low(self.i) })
+    }
+}
 
 impl<MODE> InputPin for Pin<Input<MODE>> {
     type Error = ();
@@ -92,27 +408,487 @@ impl<MODE> InputPin for Pin<Input<MODE>> {
     }
 
     fn is_low(&self) -> Result<bool, ()> {
-        Ok(unsafe { (*self.port).is_low(self.i) })
+        Ok(unsafe { (*GPIOA::ptr()).is_low(self.i) })
     }
 }
 
-macro_rules! gpio_trait {
-    ($gpiox:ident) => {
-        impl GpioRegExt for crate::swm050::$gpiox::RegisterBlock {
-            fn is_low(&self, pos: u8) -> bool {
-                self.dat.read().bits() & (1 << pos) != 0
+impl<MODE> InterruptPin for Pin<Input<MODE>> {
+    fn listen(&mut self, trigger: Trigger, cs: &CriticalSection) {
+        unsafe { (*GPIOA::ptr()).listen(self.i, trigger, cs) }
+    }
+
+    fn unlisten(&mut self, cs: &CriticalSection) {
+        unsafe { (*GPIOA::ptr()).unlisten(self.i, cs) }
+    }
+
+    fn is_interrupt_pending(&self) -> bool {
+        unsafe { (*GPIOA::ptr()).is_interrupt_pending(self.i) }
+    }
+
+    fn clear_interrupt_pending_bit(&mut self) {
+        unsafe { (*GPIOA::ptr()).clear_interrupt_pending_bit(self.i) }
+    }
+}
+
+#[cfg(feature = "eh1")]
+impl<MODE> embedded_hal_1::digital::ErrorType for Pin<MODE> {
+    type Error = Infallible;
+}
+
+#[cfg(feature = "eh1")]
+impl embedded_hal_1::digital::OutputPin for Pin<Output<PushPull>> {
+    fn set_high(&mut self) -> Result<(), Infallible> {
+        unsafe { (*GPIOA::ptr()).set_high(self.i) }
+        Ok(())
+    }
+
+    fn set_low(&mut self) -> Result<(), Infallible> {
+        unsafe { (*GPIOA::ptr()).set_low(self.i) }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "eh1")]
+impl embedded_hal_1::digital::StatefulOutputPin for Pin<Output<PushPull>> {
+    fn is_set_high(&mut self) -> Result<bool, Infallible> {
+        Ok(unsafe { !(*GPIOA::ptr()).is_set_low(self.i) })
+    }
+
+    fn is_set_low(&mut self) -> Result<bool, Infallible> {
+        Ok(unsafe { (*GPIOA::ptr()).is_set_low(self.i) })
+    }
+
+    fn toggle(&mut self) -> Result<(), Infallible> {
+        unsafe { (*GPIOA::ptr()).toggle(self.i) }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "eh1")]
+impl embedded_hal_1::digital::OutputPin for Pin<Output<OpenDrain>> {
+    fn set_high(&mut self) -> Result<(), Infallible> {
+        unsafe { (*GPIOA::ptr()).release(self.i) }
+        Ok(())
+    }
+
+    fn set_low(&mut self) -> Result<(), Infallible> {
+        unsafe { (*GPIOA::ptr()).drive_low(self.i) }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "eh1")]
+impl embedded_hal_1::digital::StatefulOutputPin for Pin<Output<OpenDrain>> {
+    fn is_set_high(&mut self) -> Result<bool, Infallible> {
+        Ok(unsafe { !(*GPIOA::ptr()).is_output(self.i) })
+    }
+
+    fn is_set_low(&mut self) -> Result<bool, Infallible> {
+        Ok(unsafe { (*GPIOA::ptr()).is_output(self.i) })
+    }
+}
+
+#[cfg(feature = "eh1")]
+impl embedded_hal_1::digital::InputPin for Pin<Output<OpenDrain>> {
+    fn is_high(&mut self) -> Result<bool, Infallible> {
+        Ok(unsafe { !(*GPIOA::ptr()).is_low(self.i) })
+    }
+
+    fn is_low(&mut self) -> Result<bool, Infallible> {
+        Ok(unsafe { (*GPIOA::ptr()).is_low(self.i) })
+    }
+}
+
+#[cfg(feature = "eh1")]
+impl<MODE> embedded_hal_1::digital::InputPin for Pin<Input<MODE>> {
+    fn is_high(&mut self) -> Result<bool, Infallible> {
+        Ok(unsafe { !(*GPIOA::ptr()).is_low(self.i) })
+    }
+
+    fn is_low(&mut self) -> Result<bool, Infallible> {
+        Ok(unsafe { (*GPIOA::ptr()).is_low(self.i) })
+    }
+}
+
+impl<MODE> Pin<MODE> {
+    /// Configures the pin to operate as a push pull output pin with the given level
+    ///
+    /// The level is set before the output is enabled, so the pin never drives the previous
+    /// contents of the data register
+    pub fn into_push_pull_output_in_state(
+        self,
+        state: PinState,
+        cs: &CriticalSection,
+    ) -> Pin<Output<PushPull>> {
+        into_gpio_pad(self.i, cs);
+        let gpio = unsafe { &*GPIOA::ptr() };
+        match state {
+            PinState::High => gpio.set_high(self.i),
+            PinState::Low => gpio.set_low(self.i),
+        }
+        gpio.set_output(self.i);
+        Pin {
+            i: self.i,
+            _mode: PhantomData,
+        }
+    }
+
+    /// Turns the pin into a `FlexPin`, starting out as floating input
+    pub fn into_flex(self, cs: &CriticalSection) -> FlexPin {
+        into_gpio_pad(self.i, cs);
+        let gpio = unsafe { &*GPIOA::ptr() };
+        gpio.set_input(self.i);
+        FlexPin {
+            i: self.i,
+            mode: FlexMode::Input,
+            high: !gpio.is_set_low(self.i),
+        }
+    }
+}
+
+/// Pull resistor of a pad
+///
+/// The pads only have a pull-up. Besides it and the input buffer, `PORT` has no pad settings,
+/// there are no pull-down, Schmitt trigger, drive strength or input filter registers (SWM050
+/// user manual, `PORT` register map).
+#[derive(Clone, Copy, PartialEq)]
+pub enum Pull {
+    /// No pull resistor
+    None,
+    /// Pull-up resistor
+    Up,
+}
+
+/// Connects the pad to the GPIO block, enables its input buffer and disables the pull-up
+fn into_gpio_pad(pos: u8, cs: &CriticalSection) {
+    into_gpio_pads(1 << pos, cs);
+}
+
+/// Connects the pads selected by `mask` to the GPIO block, enables their input buffers and
+/// disables the pull-ups
+fn into_gpio_pads(mask: u32, cs: &CriticalSection) {
+    let sel_mask = (0..16)
+        .filter(|pos| mask & (1 << pos) != 0)
+        .fold(0, |sel_mask, pos| sel_mask | (0b11 << (pos * 2)));
+    unsafe {
+        (*PORT::ptr())
+            .porta_sel
+            .modify(|r, w| w.bits(r.bits() & !sel_mask));
+    }
+    set_pads_pull(mask, Pull::None, cs);
+    set_pads_input_enabled(mask, true, cs);
+}
+
+/// Sets the pull resistor of the pad
+fn set_pad_pull(pos: u8, pull: Pull, cs: &CriticalSection) {
+    set_pads_pull(1 << pos, pull, cs);
+}
+
+/// Sets the pull resistors of the pads selected by `mask`
+fn set_pads_pull(mask: u32, pull: Pull, _cs: &CriticalSection) {
+    unsafe {
+        (*PORT::ptr()).porta_pullup.modify(|r, w| match pull {
+            Pull::None => w.bits(r.bits() & !mask),
+            Pull::Up => w.bits(r.bits() | mask),
+        });
+    }
+}
+
+/// Enables or disables the input buffer of the pad
+fn set_pad_input_enabled(pos: u8, enabled: bool, cs: &CriticalSection) {
+    set_pads_input_enabled(1 << pos, enabled, cs);
+}
+
+/// Enables or disables the input buffers of the pads selected by `mask`
+fn set_pads_input_enabled(mask: u32, enabled: bool, _cs: &CriticalSection) {
+    unsafe {
+        (*PORT::ptr()).porta_inen.modify(|r, w| {
+            if enabled {
+                w.bits(r.bits() | mask)
+            } else {
+                w.bits(r.bits() & !mask)
             }
+        });
+    }
+}
+
+/// Mode conversions of several erased pins at once
+///
+/// Each register is only written once for all pins
+pub trait PinArrayExt<const N: usize> {
+    /// Configures the pins to operate as floating input pins
+    fn into_floating_inputs(self, cs: &CriticalSection) -> [Pin<Input<Floating>>; N];
+
+    /// Configures the pins to operate as pulled up input pins
+    fn into_pull_up_inputs(self, cs: &CriticalSection) -> [Pin<Input<PullUp>>; N];
+
+    /// Configures the pins to operate as push pull output pins
+    fn into_push_pull_outputs(self, cs: &CriticalSection) -> [Pin<Output<PushPull>>; N];
+
+    /// Configures the pins to operate as emulated open drain output pins, starting out released
+    fn into_open_drain_outputs(self, cs: &CriticalSection) -> [Pin<Output<OpenDrain>>; N];
+}
+
+impl<MODE, const N: usize> PinArrayExt<N> for [Pin<MODE>; N] {
+    fn into_floating_inputs(self, cs: &CriticalSection) -> [Pin<Input<Floating>>; N] {
+        let mask = pin_mask(&self);
+        into_gpio_pads(mask, cs);
+        unsafe { (*GPIOA::ptr()).set_bits(GpioRegister::Dir, mask, false) };
+        self.map(Pin::into_mode)
+    }
+
+    fn into_pull_up_inputs(self, cs: &CriticalSection) -> [Pin<Input<PullUp>>; N] {
+        let mask = pin_mask(&self);
+        into_gpio_pads(mask, cs);
+        set_pads_pull(mask, Pull::Up, cs);
+        unsafe { (*GPIOA::ptr()).set_bits(GpioRegister::Dir, mask, false) };
+        self.map(Pin::into_mode)
+    }
+
+    fn into_push_pull_outputs(self, cs: &CriticalSection) -> [Pin<Output<PushPull>>; N] {
+        let mask = pin_mask(&self);
+        into_gpio_pads(mask, cs);
+        unsafe { (*GPIOA::ptr()).set_bits(GpioRegister::Dir, mask, true) };
+        self.map(Pin::into_mode)
+    }
+
+    fn into_open_drain_outputs(self, cs: &CriticalSection) -> [Pin<Output<OpenDrain>>; N] {
+        let mask = pin_mask(&self);
+        into_gpio_pads(mask, cs);
+        let gpio = unsafe { &*GPIOA::ptr() };
+        gpio.set_bits(GpioRegister::Dir, mask, false);
+        gpio.set_bits(GpioRegister::Dat, mask, false);
+        self.map(Pin::into_mode)
+    }
+}
 
-            fn is_set_low(&self, pos: u8) -> bool {
-                self.dat.read().bits() & (1 << pos) == 0
+/// Returns the mask of the given pins
+fn pin_mask<MODE>(pins: &[Pin<MODE>]) -> u32 {
+    pins.iter().fold(0, |mask, pin| mask | (1 << pin.i))
+}
+
+/// Runtime mode of a `FlexPin`
+#[derive(Clone, Copy, PartialEq)]
+pub enum FlexMode {
+    /// Floating input
+    Input,
+    /// Push pull output
+    Output,
+    /// Emulated open drain output
+    ///
+    /// A high level releases the line by switching the pin to input, a low level drives it low
+    OpenDrain,
+}
+
+/// Pin whose mode is tracked at runtime instead of in the type
+///
+/// This is useful for bidirectional protocols, which have to switch between input and output
+/// all the time. While in input or open drain mode, `set_high` and `set_low` also set the level
+/// that gets driven once the pin is switched to push pull output.
+pub struct FlexPin {
+    i: u8,
+    mode: FlexMode,
+    /// Level requested by the last `set_high` or `set_low`
+    high: bool,
+}
+
+impl FlexPin {
+    /// Returns the current mode of the pin
+    pub fn mode(&self) -> FlexMode {
+        self.mode
+    }
+
+    /// Switches the pin to floating input
+    pub fn set_as_input(&mut self) {
+        free(|_| unsafe { (*GPIOA::ptr()).set_input(self.i) });
+        self.mode = FlexMode::Input;
+    }
+
+    /// Switches the pin to push pull output, driving the last set level
+    pub fn set_as_output(&mut self) {
+        let gpio = unsafe { &*GPIOA::ptr() };
+        free(|_| {
+            // The open drain emulation keeps `dat` low, so the level has to be written again
+            if self.high {
+                gpio.set_high(self.i);
+            } else {
+                gpio.set_low(self.i);
             }
+            gpio.set_output(self.i);
+        });
+        self.mode = FlexMode::Output;
+    }
+
+    /// Switches the pin to open drain output, starting out released
+    pub fn set_as_open_drain(&mut self) {
+        unsafe { (*GPIOA::ptr()).release(self.i) };
+        self.mode = FlexMode::OpenDrain;
+        self.high = true;
+    }
+
+    /// Erases the mode tracking, turning this back into an input pin
+    pub fn into_floating_input(mut self) -> Pin<Input<Floating>> {
+        self.set_as_input();
+        Pin {
+            i: self.i,
+            _mode: PhantomData,
+        }
+    }
+}
+
+impl OutputPin for FlexPin {
+    type Error = ();
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.high = true;
+        match self.mode {
+            FlexMode::OpenDrain => unsafe { (*GPIOA::ptr()).release(self.i) },
+            _ => unsafe { (*GPIOA::ptr()).set_high(self.i) },
+        }
+        Ok(())
+    }
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.high = false;
+        match self.mode {
+            FlexMode::OpenDrain => unsafe { (*GPIOA::ptr()).drive_low(self.i) },
+            _ => unsafe { (*GPIOA::ptr()).set_low(self.i) },
+        }
+        Ok(())
+    }
+}
+
+impl InputPin for FlexPin {
+    type Error = ();
+
+    fn is_high(&self) -> Result<bool, ()> {
+        self.is_low().map(|low| !low)
+    }
+
+    fn is_low(&self) -> Result<bool, ()> {
+        Ok(unsafe { (*GPIOA::ptr()).is_low(self.i) })
+    }
+}
+
+#[cfg(feature = "eh1")]
+impl embedded_hal_1::digital::ErrorType for FlexPin {
+    type Error = Infallible;
+}
+
+#[cfg(feature = "eh1")]
+impl embedded_hal_1::digital::OutputPin for FlexPin {
+    fn set_high(&mut self) -> Result<(), Infallible> {
+        OutputPin::set_high(self).ok();
+        Ok(())
+    }
+
+    fn set_low(&mut self) -> Result<(), Infallible> {
+        OutputPin::set_low(self).ok();
+        Ok(())
+    }
+}
+
+#[cfg(feature = "eh1")]
+impl embedded_hal_1::digital::InputPin for FlexPin {
+    fn is_high(&mut self) -> Result<bool, Infallible> {
+        Ok(unsafe { !(*GPIOA::ptr()).is_low(self.i) })
+    }
+
+    fn is_low(&mut self) -> Result<bool, Infallible> {
+        Ok(unsafe { (*GPIOA::ptr()).is_low(self.i) })
+    }
+}
+
+/// Group of push pull output pins, which are updated with a single register write
+///
+/// The bits of the masks and values passed to `write_bits` and `read_bits` correspond to the
+/// pin numbers of GPIOA. Bits of pins which don't belong to the bus are ignored, so the bus
+/// can't change any pins it doesn't own.
+pub struct PortBus<const N: usize> {
+    pins: [Pin<Output<PushPull>>; N],
+    mask: u32,
+}
+
+impl<const N: usize> PortBus<N> {
+    /// Creates a bus from the given pins
+    ///
+    /// The first pin is the least significant bit for `write` and `read`
+    pub fn new(pins: [Pin<Output<PushPull>>; N]) -> Self {
+        let mask = pin_mask(&pins);
+        PortBus { pins, mask }
+    }
 
-            fn set_high(&self, pos: u8) {
-                unsafe { self.dat.modify(|r, w| w.bits(r.bits() | (1 << pos))) };
+    /// Returns the mask of the pins belonging to the bus
+    pub fn mask(&self) -> u32 {
+        self.mask
+    }
+
+    /// Sets the pins selected by `mask` to the corresponding bits of `value`
+    ///
+    /// All pins change at the same time
+    pub fn write_bits(&mut self, mask: u32, value: u32) {
+        unsafe { (*GPIOA::ptr()).write_bits(mask & self.mask, value) }
+    }
+
+    /// Returns the levels of the pins selected by `mask`
+    pub fn read_bits(&self, mask: u32) -> u32 {
+        unsafe { (*GPIOA::ptr()).read_bits(mask & self.mask) }
+    }
+
+    /// Writes `value` to the bus, bit `n` of `value` is output on the `n`th pin
+    pub fn write(&mut self, value: u32) {
+        let bits = self
+            .pins
+            .iter()
+            .enumerate()
+            .fold(0, |bits, (n, pin)| bits | (((value >> n) & 1) << pin.i));
+        self.write_bits(self.mask, bits);
+    }
+
+    /// Reads the bus, the `n`th pin is returned in bit `n`
+    pub fn read(&self) -> u32 {
+        let bits = self.read_bits(self.mask);
+        self.pins
+            .iter()
+            .enumerate()
+            .fold(0, |value, (n, pin)| value | (((bits >> pin.i) & 1) << n))
+    }
+
+    /// Releases the pins
+    pub fn release(self) -> [Pin<Output<PushPull>>; N] {
+        self.pins
+    }
+}
+
+macro_rules! gpio_trait {
+    ($gpiox:ident) => {
+        impl GpioRegisters for crate::swm050::$gpiox::RegisterBlock {
+            fn read(&self, reg: GpioRegister) -> u32 {
+                match reg {
+                    GpioRegister::Dat => self.dat.read().bits(),
+                    GpioRegister::Dir => self.dir.read().bits(),
+                    GpioRegister::IntEn => self.inten.read().bits(),
+                    GpioRegister::IntMask => self.intmask.read().bits(),
+                    GpioRegister::IntType => self.inttype.read().bits(),
+                    GpioRegister::IntPol => self.intpol.read().bits(),
+                    GpioRegister::IntStat => self.intstat.read().bits(),
+                    GpioRegister::IntClr => 0,
+                }
             }
 
-            fn set_low(&self, pos: u8) {
-                unsafe { self.dat.modify(|r, w| w.bits(r.bits() & !(1 << pos))) };
+            fn write(&self, reg: GpioRegister, bits: u32) {
+                unsafe {
+                    match reg {
+                        GpioRegister::Dat => self.dat.write(|w| w.bits(bits)),
+                        GpioRegister::Dir => self.dir.write(|w| w.bits(bits)),
+                        GpioRegister::IntEn => self.inten.write(|w| w.bits(bits)),
+                        GpioRegister::IntMask => self.intmask.write(|w| w.bits(bits)),
+                        GpioRegister::IntType => self.inttype.write(|w| w.bits(bits)),
+                        GpioRegister::IntPol => self.intpol.write(|w| w.bits(bits)),
+                        GpioRegister::IntStat => (),
+                        GpioRegister::IntClr => self.intclr.write(|w| w.bits(bits)),
+                    }
+                }
             }
         }
     };
@@ -120,21 +896,336 @@ macro_rules! gpio_trait {
 
 gpio_trait!(gpioa);
 
+macro_rules! gpio_pin {
+    ($GPIOX:ident, $PXi:ident, $i:expr, $sel:ident, [
+        $($AFi:ident: ($into_afi:ident, $afi:ident),)*
+    ]) => {
+        /// Pin
+        pub struct $PXi<MODE> {
+            _mode: PhantomData<MODE>,
+        }
+
+        impl<MODE> $PXi<MODE> {
+            /// Configures the pin to operate as a floating input pin
+            pub fn into_floating_input(self, cs: &CriticalSection) -> $PXi<Input<Floating>> {
+                into_gpio_pad($i, cs);
+                unsafe { (*$GPIOX::ptr()).set_input($i) };
+                $PXi { _mode: PhantomData }
+            }
+
+            /// Configures the pin to operate as a pulled up input pin
+            pub fn into_pull_up_input(self, cs: &CriticalSection) -> $PXi<Input<PullUp>> {
+                into_gpio_pad($i, cs);
+                set_pad_pull($i, Pull::Up, cs);
+                unsafe { (*$GPIOX::ptr()).set_input($i) };
+                $PXi { _mode: PhantomData }
+            }
+
+            /// Configures the pin to operate as an push pull output pin
+            pub fn into_push_pull_output(self, cs: &CriticalSection) -> $PXi<Output<PushPull>> {
+                into_gpio_pad($i, cs);
+                unsafe { (*$GPIOX::ptr()).set_output($i) };
+                $PXi { _mode: PhantomData }
+            }
+
+            /// Configures the pin to operate as a push pull output pin with the given level
+            ///
+            /// The level is set before the output is enabled, so the pin never drives the
+            /// previous contents of the data register
+            pub fn into_push_pull_output_in_state(
+                self,
+                state: PinState,
+                cs: &CriticalSection,
+            ) -> $PXi<Output<PushPull>> {
+                self.downgrade().into_push_pull_output_in_state(state, cs);
+                $PXi { _mode: PhantomData }
+            }
+
+            /// Configures the pin to operate as an emulated open drain output pin
+            ///
+            /// The pin starts out released
+            pub fn into_open_drain_output(self, cs: &CriticalSection) -> $PXi<Output<OpenDrain>> {
+                into_gpio_pad($i, cs);
+                unsafe { (*$GPIOX::ptr()).release($i) };
+                $PXi { _mode: PhantomData }
+            }
+
+            $(
+                /// Configures the pin to operate in the given alternate function mode
+                pub fn $into_afi(self, port: &mut PORT) -> $PXi<Alternate<$AFi>> {
+                    // Written in a critical section like in the other mode conversions
+                    cortex_m::interrupt::free(|_| port.porta_sel.modify(|_, w| w.$sel().$afi()));
+                    $PXi { _mode: PhantomData }
+                }
+            )*
+        }
+
+        impl<MODE> $PXi<MODE> {
+            /// Enables or disables the input buffer of the pad
+            ///
+            /// Disabling it saves power when the pin is used as analog input or not at all, but
+            /// reading the pin doesn't work anymore. Converting the pin to another GPIO mode
+            /// enables it again.
+            pub fn set_input_enabled(&mut self, enabled: bool, cs: &CriticalSection) {
+                set_pad_input_enabled($i, enabled, cs);
+            }
+
+            /// Turns the pin into a `FlexPin`, starting out as floating input
+            pub fn into_flex(self, cs: &CriticalSection) -> FlexPin {
+                self.downgrade().into_flex(cs)
+            }
+
+            /// Erases the pin number from the type
+            ///
+            /// This is useful when you want to collect the pins into an array where you
+            /// need all the elements to have the same type
+            pub const fn downgrade(self) -> Pin<MODE> {
+                Pin {
+                    i: $i,
+                    _mode: PhantomData,
+                }
+            }
+        }
+
+        impl $PXi<Input<Floating>> {
+            /// Sets the pull resistor of the pad
+            ///
+            /// The pin stays an `Input<Floating>`, `into_pull_up_input` also tracks the pull-up
+            /// in the type.
+            pub fn with_pull(self, pull: Pull, cs: &CriticalSection) -> Self {
+                set_pad_pull($i, pull, cs);
+                self
+            }
+        }
+
+        impl<AF> $PXi<Alternate<AF>> {
+            /// Sets the pull resistor of the pad
+            pub fn with_pull(self, pull: Pull, cs: &CriticalSection) -> Self {
+                set_pad_pull($i, pull, cs);
+                self
+            }
+        }
+
+        impl $PXi<Output<OpenDrain>> {
+            /// Sets the pull resistor of the pad
+            ///
+            /// The internal pull-up is weak, but may be enough for a short bus
+            pub fn with_pull(self, pull: Pull, cs: &CriticalSection) -> Self {
+                set_pad_pull($i, pull, cs);
+                self
+            }
+        }
+
+        impl StatefulOutputPin for $PXi<Output<PushPull>> {
+            fn is_set_high(&self) -> Result<bool, ()> {
+                self.is_set_low().map(|low| !low)
+            }
+
+            fn is_set_low(&self) -> Result<bool, ()> {
+                Ok(unsafe { (*$GPIOX::ptr()).is_set_low($i) })
+            }
+        }
+
+        impl OutputPin for $PXi<Output<PushPull>> {
+            type Error = ();
+            fn set_high(&mut self) -> Result<(), ()> {
+                unsafe { (*$GPIOX::ptr()).set_high($i) }
+                Ok(())
+            }
+
+            fn set_low(&mut self) -> Result<(), ()>{
+                unsafe { (*$GPIOX::ptr()).set_low($i) }
+                Ok(())
+            }
+        }
+
+        impl ToggleableOutputPin for $PXi<Output<PushPull>> {
+            type Error = ();
+
+            fn toggle(&mut self) -> Result<(), ()> {
+                unsafe { (*$GPIOX::ptr()).toggle($i) }
+                Ok(())
+            }
+        }
+
+        impl StatefulOutputPin for $PXi<Output<OpenDrain>> {
+            fn is_set_high(&self) -> Result<bool, ()> {
+                self.is_set_low().map(|low| !low)
+            }
+
+            fn is_set_low(&self) -> Result<bool, ()> {
+                Ok(unsafe { (*$GPIOX::ptr()).is_output($i) })
+            }
+        }
+
+        impl OutputPin for $PXi<Output<OpenDrain>> {
+            type Error = ();
+            fn set_high(&mut self) -> Result<(), ()> {
+                unsafe { (*$GPIOX::ptr()).release($i) }
+                Ok(())
+            }
+
+            fn set_low(&mut self) -> Result<(), ()>{
+                unsafe { (*$GPIOX::ptr()).drive_low($i) }
+                Ok(())
+            }
+        }
+
+        impl toggleable::Default for $PXi<Output<OpenDrain>> {}
+
+        impl InputPin for $PXi<Output<OpenDrain>> {
+            type Error = ();
+            fn is_high(&self) -> Result<bool, ()> {
+                self.is_low().map(|low| !low)
+            }
+
+            fn is_low(&self) -> Result<bool, ()> {
+                Ok(unsafe { (*$GPIOX::ptr()).is_low($i) })
+            }
+        }
+
+        impl<MODE> InputPin for $PXi<Input<MODE>> {
+            type Error = ();
+            fn is_high(&self) -> Result<bool, ()> {
+                self.is_low().map(|low| !low)
+            }
+
+            fn is_low(&self) -> Result<bool, ()> {
+                Ok(unsafe { (*$GPIOX::ptr()).is_low($i) })
+            }
+        }
+
+        #[cfg(feature = "eh1")]
+        impl<MODE> embedded_hal_1::digital::ErrorType for $PXi<MODE> {
+            type Error = Infallible;
+        }
+
+        #[cfg(feature = "eh1")]
+        impl embedded_hal_1::digital::OutputPin for $PXi<Output<PushPull>> {
+            fn set_high(&mut self) -> Result<(), Infallible> {
+                unsafe { (*$GPIOX::ptr()).set_high($i) }
+                Ok(())
+            }
+
+            fn set_low(&mut self) -> Result<(), Infallible> {
+                unsafe { (*$GPIOX::ptr()).set_low($i) }
+                Ok(())
+            }
+        }
+
+        #[cfg(feature = "eh1")]
+        impl embedded_hal_1::digital::StatefulOutputPin for $PXi<Output<PushPull>> {
+            fn is_set_high(&mut self) -> Result<bool, Infallible> {
+                Ok(unsafe { !(*$GPIOX::ptr()).is_set_low($i) })
+            }
+
+            fn is_set_low(&mut self) -> Result<bool, Infallible> {
+                Ok(unsafe { (*$GPIOX::ptr()).is_set_low($i) })
+            }
+
+            fn toggle(&mut self) -> Result<(), Infallible> {
+                unsafe { (*$GPIOX::ptr()).toggle($i) }
+                Ok(())
+            }
+        }
+
+        #[cfg(feature = "eh1")]
+        impl embedded_hal_1::digital::OutputPin for $PXi<Output<OpenDrain>> {
+            fn set_high(&mut self) -> Result<(), Infallible> {
+                unsafe { (*$GPIOX::ptr()).release($i) }
+                Ok(())
+            }
+
+            fn set_low(&mut self) -> Result<(), Infallible> {
+                unsafe { (*$GPIOX::ptr()).drive_low($i) }
+                Ok(())
+            }
+        }
+
+        #[cfg(feature = "eh1")]
+        impl embedded_hal_1::digital::StatefulOutputPin for $PXi<Output<OpenDrain>> {
+            fn is_set_high(&mut self) -> Result<bool, Infallible> {
+                Ok(unsafe { !(*$GPIOX::ptr()).is_output($i) })
+            }
+
+            fn is_set_low(&mut self) -> Result<bool, Infallible> {
+                Ok(unsafe { (*$GPIOX::ptr()).is_output($i) })
+            }
+        }
+
+        #[cfg(feature = "eh1")]
+        impl embedded_hal_1::digital::InputPin for $PXi<Output<OpenDrain>> {
+            fn is_high(&mut self) -> Result<bool, Infallible> {
+                Ok(unsafe { !(*$GPIOX::ptr()).is_low($i) })
+            }
+
+            fn is_low(&mut self) -> Result<bool, Infallible> {
+                Ok(unsafe { (*$GPIOX::ptr()).is_low($i) })
+            }
+        }
+
+        #[cfg(feature = "eh1")]
+        impl<MODE> embedded_hal_1::digital::InputPin for $PXi<Input<MODE>> {
+            fn is_high(&mut self) -> Result<bool, Infallible> {
+                Ok(unsafe { !(*$GPIOX::ptr()).is_low($i) })
+            }
+
+            fn is_low(&mut self) -> Result<bool, Infallible> {
+                Ok(unsafe { (*$GPIOX::ptr()).is_low($i) })
+            }
+        }
+
+        impl<MODE> InterruptPin for $PXi<Input<MODE>> {
+            fn listen(&mut self, trigger: Trigger, cs: &CriticalSection) {
+                unsafe { (*$GPIOX::ptr()).listen($i, trigger, cs) }
+            }
+
+            fn unlisten(&mut self, cs: &CriticalSection) {
+                unsafe { (*$GPIOX::ptr()).unlisten($i, cs) }
+            }
+
+            fn is_interrupt_pending(&self) -> bool {
+                unsafe { (*$GPIOX::ptr()).is_interrupt_pending($i) }
+            }
+
+            fn clear_interrupt_pending_bit(&mut self) {
+                unsafe { (*$GPIOX::ptr()).clear_interrupt_pending_bit($i) }
+            }
+        }
+    };
+}
+
 #[allow(unused)]
 macro_rules! gpio {
     ($GPIOX:ident, $gpiox:ident, [
-        $($PXi:ident: ($pxi:ident, $i:expr, $MODE:ty),)+
+        $($PXi:ident: ($pxi:ident, $i:expr, $sel:ident, $MODE:ty, [
+            $($AFi:ident: ($into_afi:ident, $afi:ident),)*
+        ]),)+
+    ], swd: [
+        $($SXi:ident: ($sxi:ident, $si:expr, $ssel:ident, $SMODE:ty, [
+            $($SAFi:ident: ($into_safi:ident, $safi:ident),)*
+        ]),)+
     ]) => {
         /// GPIO
         pub mod $gpiox {
             use core::marker::PhantomData;
 
-            use crate::swm050::$GPIOX;
-            use embedded_hal::digital::v2::{toggleable, InputPin, OutputPin, StatefulOutputPin};
+            use crate::swm050::{Interrupt, $GPIOX, NVIC, PORT};
+            use embedded_hal::digital::v2::{
+                toggleable, InputPin, OutputPin, PinState, StatefulOutputPin, ToggleableOutputPin,
+            };
 
             use cortex_m::interrupt::CriticalSection;
 
-            use super::{Floating, GpioExt, GpioRegExt, Input, Output, PullUp, Pin};
+            #[cfg(feature = "eh1")]
+            use core::convert::Infallible;
+
+            #[allow(unused)]
+            use super::{
+                into_gpio_pad, set_pad_input_enabled, set_pad_pull, Alternate, FlexPin, Floating,
+                GpioExt, GpioRegExt, Input, InterruptPin, OpenDrain, Output, Pin, Pull, PullUp,
+                PushPull, Trigger, AF0, AF1, AF2, AF3, AF4, AF5, AF6, AF7,
+            };
 
             /// GPIO parts
             pub struct Parts {
@@ -142,6 +1233,45 @@ macro_rules! gpio {
                     /// Pin
                     pub $pxi: $PXi<$MODE>,
                 )+
+                /// Pins used by the debug port
+                pub swd: SwdPins,
+            }
+
+            impl Parts {
+                /// Erases the pin numbers of all pins except the SWD pins
+                ///
+                /// The pins are in ascending order. Unlike the other pins, the SWD pins are
+                /// returned as `SwdPins`, so the debug port isn't disabled by accident. To get an
+                /// array of all pins, pass the result of `SwdPins::release_swd` and this array
+                /// to `merge_swd`.
+                pub fn into_erased_array(self) -> ([Pin<Input<Floating>>; PINS], SwdPins) {
+                    ([$(self.$pxi.downgrade(),)+], self.swd)
+                }
+            }
+
+            /// Number of pins in `Parts`, excluding the SWD pins
+            const PINS: usize = [$($i,)+].len();
+
+            /// Number of SWD pins
+            const SWD_PINS: usize = [$($si,)+].len();
+
+            /// Adds the released SWD pins to the array returned by `Parts::into_erased_array`
+            ///
+            /// The SWD pins are configured as floating inputs, which disconnects the debugger
+            /// until the next reset. The pins are in ascending order.
+            pub fn merge_swd(
+                pins: [Pin<Input<Floating>>; PINS],
+                swd: ($($SXi<$SMODE>,)+),
+                cs: &CriticalSection,
+            ) -> [Pin<Input<Floating>>; PINS + SWD_PINS] {
+                let [$($pxi,)+] = pins;
+                let ($($sxi,)+) = swd;
+                let mut pins = [
+                    $($pxi,)+
+                    $($sxi.into_floating_input(cs).downgrade(),)+
+                ];
+                pins.sort_unstable_by_key(|pin| pin.i);
+                pins
             }
 
             impl GpioExt for $GPIOX {
@@ -152,112 +1282,294 @@ macro_rules! gpio {
                         $(
                             $pxi: $PXi { _mode: PhantomData },
                         )+
+                        swd: SwdPins {
+                            $(
+                                $sxi: $SXi { _mode: PhantomData },
+                            )+
+                        },
                     }
                 }
             }
 
-            $(
-                /// Pin
-                pub struct $PXi<MODE> {
-                    _mode: PhantomData<MODE>,
+            /// Pins used by the debug port (SWD)
+            ///
+            /// After reset these pins are connected to the debug port. To prevent cutting off the
+            /// debugger by accident, they have to be released explicitly before they can be used
+            /// as GPIO or for another alternate function.
+            pub struct SwdPins {
+                $(
+                    $sxi: $SXi<$SMODE>,
+                )+
+            }
+
+            impl SwdPins {
+                /// Keeps the pins connected to the debug port
+                ///
+                /// This consumes the token, so the pins can't be reconfigured afterwards
+                pub fn keep_swd(self) {}
+
+                /// Releases the pins, so they can be reconfigured
+                ///
+                /// The pins are still connected to the debug port until they are switched to
+                /// another mode. Once that happens, a debugger can't attach anymore until the
+                /// next reset.
+                pub fn release_swd(self) -> ($($SXi<$SMODE>,)+) {
+                    ($(self.$sxi,)+)
                 }
+            }
 
-                impl<MODE> $PXi<MODE> {
-                    /// Configures the pin to operate as a floating input pin
-                    pub fn into_floating_input(self, _cs: &CriticalSection) -> $PXi<Input<Floating>> {
-                        unsafe {
-                            &(*$GPIOX::ptr())
-                                .dir
-                                .modify(|r, w| w.bits(r.bits() & !(1 << $i)));
-                        }
-                        $PXi { _mode: PhantomData }
-                    }
+            /// Unmasks the port interrupt in the NVIC
+            pub fn unmask_interrupt(nvic: &mut NVIC) {
+                // `NVIC::unmask` replaces this from cortex-m 0.6.1 on, but doesn't exist in 0.5
+                #[allow(deprecated)]
+                nvic.enable(Interrupt::$GPIOX);
+            }
 
-                    /// Configures the pin to operate as a pulled up input pin
-                    pub fn into_pull_up_input(self, _cs: &CriticalSection) -> $PXi<Input<PullUp>> {
-                        unimplemented!();
-                        // unsafe {
-                        //     &(*$GPIOX::ptr())
-                        //         .dir
-                        //         .modify(|r, w| w.bits(r.bits() & !(1 << $i)));
-                        // }
-                        // $PXi { _mode: PhantomData }
-                    }
+            /// Masks the port interrupt in the NVIC
+            pub fn mask_interrupt(nvic: &mut NVIC) {
+                #[allow(deprecated)]
+                nvic.disable(Interrupt::$GPIOX);
+            }
 
-                    /// Configures the pin to operate as an push pull output pin
-                    pub fn into_push_pull_output(self, _cs: &CriticalSection) -> $PXi<Output> {
-                        unsafe {
-                            &(*$GPIOX::ptr())
-                                .dir
-                                .modify(|r, w| w.bits(r.bits() | (1 << $i)));
-                        }
-                        $PXi { _mode: PhantomData }
-                    }
-                }
+            $(
+                gpio_pin!($GPIOX, $PXi, $i, $sel, [$($AFi: ($into_afi, $afi),)*]);
+            )+
+            $(
+                gpio_pin!($GPIOX, $SXi, $si, $ssel, [$($SAFi: ($into_safi, $safi),)*]);
+            )+
+        }
+    }
+}
 
-                impl<MODE> $PXi<MODE> {
-                    /// Erases the pin number from the type
-                    ///
-                    /// This is useful when you want to collect the pins into an array where you
-                    /// need all the elements to have the same type
-                    pub fn downgrade(self) -> Pin<MODE> {
-                        Pin {
-                            i: $i,
-                            port: $GPIOX::ptr() as *const dyn GpioRegExt,
-                            _mode: self._mode,
-                        }
-                    }
-                }
+// Each pin has a field in `PORT.porta_sel`, which selects between GPIO and the alternate
+// functions
+gpio!(GPIOA, gpioa, [
+    PA_0: (pa_0, 0, pa00, Input<Floating>, []),
+    PA_3: (pa_3, 3, pa03, Input<Floating>, []),
+    PA_4: (pa_4, 4, pa04, Input<Floating>, []),
+    PA_5: (pa_5, 5, pa05, Input<Floating>, []),
+    PA_6: (pa_6, 6, pa06, Input<Floating>, []),
+    PA_7: (pa_7, 7, pa07, Input<Floating>, [
+        AF2: (into_alternate_af2, tmrse1_out),
+    ]),
+    PA_8: (pa_8, 8, pa08, Input<Floating>, []),
+    PA_9: (pa_9, 9, pa09, Input<Floating>, []),
+], swd: [
+    PA_1: (pa_1, 1, pa01, Alternate<AF1>, [
+        AF1: (into_alternate_af1, swclk),
+    ]),
+    PA_2: (pa_2, 2, pa02, Alternate<AF1>, [
+        AF1: (into_alternate_af1, swdio),
+        AF2: (into_alternate_af2, tmrse0_out),
+    ]),
+]);
 
-                impl StatefulOutputPin for $PXi<Output> {
-                    fn is_set_high(&self) -> Result<bool, ()> {
-                        self.is_set_low().map(|low| !low)
-                    }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-                    fn is_set_low(&self) -> Result<bool, ()> {
-                        Ok(unsafe { (*$GPIOX::ptr()).is_set_low($i) })
-                    }
-                }
+    /// Register block backed by plain memory
+    struct FakeGpio {
+        regs: Cell<[u32; 8]>,
+    }
 
-                impl OutputPin for $PXi<Output> {
-                    type Error = ();
-                    fn set_high(&mut self) -> Result<(), ()> {
-                        unsafe { (*$GPIOX::ptr()).set_high($i) }
-                        Ok(())
-                    }
+    impl FakeGpio {
+        fn new() -> Self {
+            FakeGpio {
+                regs: Cell::new([0; 8]),
+            }
+        }
 
-                    fn set_low(&mut self) -> Result<(), ()>{
-                        unsafe { (*$GPIOX::ptr()).set_low($i) }
-                        Ok(())
-                    }
-                }
+        fn get(&self, reg: GpioRegister) -> u32 {
+            self.regs.get()[reg as usize]
+        }
 
-                impl toggleable::Default for $PXi<Output> {}
+        fn set(&self, reg: GpioRegister, bits: u32) {
+            let mut regs = self.regs.get();
+            regs[reg as usize] = bits;
+            self.regs.set(regs);
+        }
+    }
 
-                impl<MODE> InputPin for $PXi<Input<MODE>> {
-                    type Error = ();
-                    fn is_high(&self) -> Result<bool, ()> {
-                        self.is_low().map(|low| !low)
-                    }
+    impl GpioRegisters for FakeGpio {
+        fn read(&self, reg: GpioRegister) -> u32 {
+            match reg {
+                GpioRegister::IntClr => 0,
+                _ => self.get(reg),
+            }
+        }
 
-                    fn is_low(&self) -> Result<bool, ()> {
-                        Ok(unsafe { (*$GPIOX::ptr()).is_low($i) })
-                    }
+        fn write(&self, reg: GpioRegister, bits: u32) {
+            match reg {
+                GpioRegister::IntStat => (),
+                GpioRegister::IntClr => {
+                    // Remember the last write, so the tests can check it
+                    self.set(GpioRegister::IntClr, bits);
+                    self.set(
+                        GpioRegister::IntStat,
+                        self.get(GpioRegister::IntStat) & !bits,
+                    );
                 }
-            )+
+                _ => self.set(reg, bits),
+            }
         }
     }
-}
 
-gpio!(GPIOA, gpioa, [
-    PA_0: (pa_0, 0, Input<Floating>),
-    PA_1: (pa_1, 1, Input<Floating>),
-    PA_2: (pa_2, 2, Input<Floating>),
-    PA_3: (pa_3, 3, Input<Floating>),
-    PA_4: (pa_4, 4, Input<Floating>),
-    PA_5: (pa_5, 5, Input<Floating>),
-    PA_6: (pa_6, 6, Input<Floating>),
-    PA_7: (pa_7, 7, Input<Floating>),
-    PA_8: (pa_8, 8, Input<Floating>),
-    PA_9: (pa_9, 9, Input<Floating>),
-]);
+    #[test]
+    fn is_low_is_true_for_cleared_bit() {
+        let gpio = FakeGpio::new();
+        gpio.set(GpioRegister::Dat, !(1 << 3));
+        assert!(gpio.is_low(3));
+        assert!(gpio.is_set_low(3));
+
+        gpio.set(GpioRegister::Dat, 1 << 3);
+        assert!(!gpio.is_low(3));
+        assert!(!gpio.is_set_low(3));
+    }
+
+    #[test]
+    fn set_high_and_set_low_only_change_their_bit() {
+        let gpio = FakeGpio::new();
+        gpio.set(GpioRegister::Dat, 0b1010_0000);
+
+        gpio.set_high(2);
+        assert_eq!(gpio.get(GpioRegister::Dat), 0b1010_0100);
+        assert!(!gpio.is_set_low(2));
+
+        gpio.set_low(7);
+        assert_eq!(gpio.get(GpioRegister::Dat), 0b0010_0100);
+        assert!(gpio.is_set_low(7));
+
+        gpio.set_low(7);
+        assert_eq!(gpio.get(GpioRegister::Dat), 0b0010_0100);
+        assert_eq!(gpio.get(GpioRegister::Dir), 0);
+    }
+
+    #[test]
+    fn toggle_flips_only_its_bit() {
+        let gpio = FakeGpio::new();
+        gpio.set(GpioRegister::Dat, 0b1001);
+
+        gpio.toggle(1);
+        assert_eq!(gpio.get(GpioRegister::Dat), 0b1011);
+        gpio.toggle(0);
+        assert_eq!(gpio.get(GpioRegister::Dat), 0b1010);
+        gpio.toggle(1);
+        gpio.toggle(0);
+        assert_eq!(gpio.get(GpioRegister::Dat), 0b1001);
+    }
+
+    #[test]
+    fn set_bits_for_outputs_in_dir() {
+        let gpio = FakeGpio::new();
+        gpio.set(GpioRegister::Dir, 0b1);
+
+        gpio.set_output(4);
+        assert_eq!(gpio.get(GpioRegister::Dir), 0b1_0001);
+        assert!(gpio.is_output(4));
+
+        gpio.set_input(4);
+        assert_eq!(gpio.get(GpioRegister::Dir), 0b1);
+        assert!(!gpio.is_output(4));
+        assert_eq!(gpio.get(GpioRegister::Dat), 0);
+    }
+
+    #[test]
+    fn release_and_drive_low_emulate_open_drain() {
+        let gpio = FakeGpio::new();
+        // A high level latched by another pin's write has to be discarded
+        gpio.set(GpioRegister::Dat, 0b110_0000);
+        gpio.set(GpioRegister::Dir, 0b100_0001);
+
+        gpio.drive_low(6);
+        assert_eq!(gpio.get(GpioRegister::Dat), 0b010_0000);
+        assert_eq!(gpio.get(GpioRegister::Dir), 0b100_0001);
+        assert!(gpio.is_output(6));
+
+        gpio.set(GpioRegister::Dat, 0b110_0000);
+        gpio.release(6);
+        assert_eq!(gpio.get(GpioRegister::Dat), 0b010_0000);
+        assert_eq!(gpio.get(GpioRegister::Dir), 0b000_0001);
+
+        gpio.drive_low(6);
+        assert_eq!(gpio.get(GpioRegister::Dat), 0b010_0000);
+        assert_eq!(gpio.get(GpioRegister::Dir), 0b100_0001);
+    }
+
+    #[test]
+    fn write_bits_only_changes_masked_bits() {
+        let gpio = FakeGpio::new();
+        gpio.set(GpioRegister::Dat, 0b1_0001);
+
+        gpio.write_bits(0b1100, 0b0110);
+        assert_eq!(gpio.get(GpioRegister::Dat), 0b1_0101);
+        assert_eq!(gpio.read_bits(0b0110), 0b0100);
+        assert_eq!(gpio.read_bits(0b1_0000), 0b1_0000);
+    }
+
+    // All triggers are checked in one test, because `BOTH_EDGES` is shared between threads
+    #[test]
+    fn listen_sets_type_and_polarity() {
+        let gpio = FakeGpio::new();
+        let pin = 5;
+        let bit = 1 << pin;
+        gpio.set(GpioRegister::IntMask, 0xFFFF);
+
+        for &(trigger, edge, high) in &[
+            (Trigger::Rising, bit, bit),
+            (Trigger::Falling, bit, 0),
+            (Trigger::High, 0, bit),
+            (Trigger::Low, 0, 0),
+        ] {
+            gpio.set(GpioRegister::IntClr, 0);
+            free(|cs| gpio.listen(pin, trigger, cs));
+            assert_eq!(gpio.get(GpioRegister::IntType), edge);
+            assert_eq!(gpio.get(GpioRegister::IntPol), high);
+            assert_eq!(gpio.get(GpioRegister::IntEn), bit);
+            assert_eq!(gpio.get(GpioRegister::IntMask), 0xFFFF & !bit);
+            assert_eq!(gpio.get(GpioRegister::IntClr), bit);
+        }
+
+        // Both edges start with the edge leading away from the current level
+        gpio.set(GpioRegister::Dat, 0);
+        free(|cs| gpio.listen(pin, Trigger::RisingFalling, cs));
+        assert_eq!(gpio.get(GpioRegister::IntType), bit);
+        assert_eq!(gpio.get(GpioRegister::IntPol), bit);
+
+        gpio.set(GpioRegister::IntStat, bit);
+        assert!(gpio.is_interrupt_pending(pin));
+        assert!(!gpio.is_interrupt_pending(pin + 1));
+
+        // The line is high now, so the next edge is a falling one
+        gpio.set(GpioRegister::Dat, bit);
+        gpio.clear_interrupt_pending_bit(pin);
+        assert!(!gpio.is_interrupt_pending(pin));
+        assert_eq!(gpio.get(GpioRegister::IntPol), 0);
+
+        gpio.set(GpioRegister::Dat, 0);
+        gpio.clear_interrupt_pending_bit(pin);
+        assert_eq!(gpio.get(GpioRegister::IntPol), bit);
+
+        free(|cs| gpio.unlisten(pin, cs));
+        assert_eq!(gpio.get(GpioRegister::IntEn), 0);
+
+        // Single edges keep their polarity
+        free(|cs| gpio.listen(pin, Trigger::Rising, cs));
+        gpio.set(GpioRegister::Dat, bit);
+        gpio.clear_interrupt_pending_bit(pin);
+        assert_eq!(gpio.get(GpioRegister::IntPol), bit);
+        free(|cs| gpio.unlisten(pin, cs));
+    }
+
+    #[test]
+    fn downgrade_keeps_pin_number() {
+        let gpioa = unsafe { crate::swm050::Peripherals::steal() }.GPIOA.split();
+        assert_eq!(gpioa.pa_0.downgrade().i, 0);
+        assert_eq!(gpioa.pa_5.downgrade().i, 5);
+        assert_eq!(gpioa.pa_9.downgrade().i, 9);
+
+        let (pa_1, pa_2) = gpioa.swd.release_swd();
+        assert_eq!(pa_1.downgrade().i, 1);
+        assert_eq!(pa_2.downgrade().i, 2);
+    }
+}
@@ -0,0 +1,312 @@
+//! Debouncing of inputs sampled from a periodic tick
+
+use embedded_hal::digital::v2::InputPin;
+use embedded_hal::timer::CountDown;
+
+/// Settings of a `Debounced` input
+///
+/// All times are given in ticks, i.e. calls to `Debounced::update`
+#[derive(Clone, Copy)]
+pub struct DebounceConfig {
+    /// Number of consecutive samples that have to differ from the stable state before it changes
+    pub samples: u8,
+    /// Ticks the input has to be held until `Event::LongPress` is reported, 0 disables it
+    pub long_press: u16,
+    /// Ticks between `Event::Repeat`s after a long press, 0 disables them
+    pub repeat: u16,
+    /// A low level means pressed, as with a button to ground and a pull-up
+    pub active_low: bool,
+}
+
+impl Default for DebounceConfig {
+    fn default() -> Self {
+        DebounceConfig {
+            samples: 5,
+            long_press: 0,
+            repeat: 0,
+            active_low: true,
+        }
+    }
+}
+
+/// Change of a `Debounced` input
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Event {
+    /// The input became active
+    Pressed,
+    /// The input became inactive
+    Released,
+    /// The input has been active for `DebounceConfig::long_press` ticks
+    LongPress,
+    /// The input is still active, `DebounceConfig::repeat` ticks after the last long press or
+    /// repeat
+    Repeat,
+}
+
+/// Debounced input
+///
+/// `update` has to be called at a fixed rate, e.g. from a `timers::Timer` interrupt. When
+/// polling a `CountDown` from the main loop, `poll` can be used instead.
+pub struct Debounced<P> {
+    pin: P,
+    config: DebounceConfig,
+    pressed: bool,
+    count: u8,
+    held: u16,
+    long_pressed: bool,
+}
+
+impl<P> Debounced<P>
+where
+    P: InputPin,
+{
+    /// Wraps `pin`, which starts out as released
+    pub fn new(pin: P, config: DebounceConfig) -> Self {
+        Debounced {
+            pin,
+            config,
+            pressed: false,
+            count: 0,
+            held: 0,
+            long_pressed: false,
+        }
+    }
+
+    /// Samples the input once and returns the resulting event, if any
+    pub fn update(&mut self) -> Result<Option<Event>, P::Error> {
+        let active = if self.config.active_low {
+            self.pin.is_low()?
+        } else {
+            self.pin.is_high()?
+        };
+
+        if active != self.pressed {
+            self.count += 1;
+            if self.count >= self.config.samples {
+                self.pressed = active;
+                self.count = 0;
+                self.held = 0;
+                self.long_pressed = false;
+                return Ok(Some(if active {
+                    Event::Pressed
+                } else {
+                    Event::Released
+                }));
+            }
+        } else {
+            self.count = 0;
+        }
+
+        if !self.pressed || self.config.long_press == 0 {
+            return Ok(None);
+        }
+        self.held = self.held.saturating_add(1);
+        if !self.long_pressed {
+            if self.held >= self.config.long_press {
+                self.long_pressed = true;
+                self.held = 0;
+                return Ok(Some(Event::LongPress));
+            }
+        } else if self.config.repeat != 0 && self.held >= self.config.repeat {
+            self.held = 0;
+            return Ok(Some(Event::Repeat));
+        }
+        Ok(None)
+    }
+
+    /// Samples the input every time `timer` wraps
+    ///
+    /// Returns `WouldBlock` until there's an event
+    pub fn poll<C>(&mut self, timer: &mut C) -> nb::Result<Event, P::Error>
+    where
+        C: CountDown,
+    {
+        if timer.wait().is_err() {
+            return Err(nb::Error::WouldBlock);
+        }
+        match self.update() {
+            Ok(Some(event)) => Ok(event),
+            Ok(None) => Err(nb::Error::WouldBlock),
+            Err(e) => Err(nb::Error::Other(e)),
+        }
+    }
+
+    /// Returns the debounced state
+    pub fn is_pressed(&self) -> bool {
+        self.pressed
+    }
+
+    /// Releases the pin
+    pub fn release(self) -> P {
+        self.pin
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::Cell;
+    use void::Void;
+
+    /// Input returning a recorded sequence of levels, one per read
+    struct Recorded<'a> {
+        levels: &'a [u8],
+        pos: Cell<usize>,
+    }
+
+    impl<'a> Recorded<'a> {
+        fn new(levels: &'a [u8]) -> Self {
+            Recorded {
+                levels,
+                pos: Cell::new(0),
+            }
+        }
+    }
+
+    impl InputPin for Recorded<'_> {
+        type Error = ();
+
+        fn is_high(&self) -> Result<bool, ()> {
+            let pos = self.pos.get();
+            self.pos.set(pos + 1);
+            Ok(self.levels[pos] != 0)
+        }
+
+        fn is_low(&self) -> Result<bool, ()> {
+            self.is_high().map(|high| !high)
+        }
+    }
+
+    fn events(config: DebounceConfig, levels: &[u8]) -> Vec<Option<Event>> {
+        let mut input = Debounced::new(Recorded::new(levels), config);
+        levels.iter().map(|_| input.update().unwrap()).collect()
+    }
+
+    fn active_high(samples: u8, long_press: u16, repeat: u16) -> DebounceConfig {
+        DebounceConfig {
+            samples,
+            long_press,
+            repeat,
+            active_low: false,
+        }
+    }
+
+    #[test]
+    fn bounces_shorter_than_samples_are_ignored() {
+        use Event::*;
+        assert_eq!(
+            events(active_high(3, 0, 0), &[1, 0, 1, 1, 1, 0, 0, 1, 0, 0, 0]),
+            [
+                None,
+                None,
+                None,
+                None,
+                Some(Pressed),
+                None,
+                None,
+                None,
+                None,
+                None,
+                Some(Released)
+            ]
+        );
+    }
+
+    #[test]
+    fn active_low_is_pressed_on_low_level() {
+        let levels = [1, 0, 0, 1, 1];
+        let mut input = Debounced::new(
+            Recorded::new(&levels),
+            DebounceConfig {
+                samples: 2,
+                ..DebounceConfig::default()
+            },
+        );
+        assert_eq!(input.update(), Ok(None));
+        assert_eq!(input.update(), Ok(None));
+        assert!(!input.is_pressed());
+        assert_eq!(input.update(), Ok(Some(Event::Pressed)));
+        assert!(input.is_pressed());
+        assert_eq!(input.update(), Ok(None));
+        assert_eq!(input.update(), Ok(Some(Event::Released)));
+        assert!(!input.is_pressed());
+    }
+
+    #[test]
+    fn long_press_then_repeats_while_held() {
+        use Event::*;
+        assert_eq!(
+            events(active_high(1, 3, 2), &[1, 1, 1, 1, 1, 1, 1, 1, 0]),
+            [
+                Some(Pressed),
+                None,
+                None,
+                Some(LongPress),
+                None,
+                Some(Repeat),
+                None,
+                Some(Repeat),
+                Some(Released)
+            ]
+        );
+    }
+
+    #[test]
+    fn no_repeat_without_repeat_time() {
+        use Event::*;
+        assert_eq!(
+            events(active_high(1, 2, 0), &[1, 1, 1, 1, 1]),
+            [Some(Pressed), None, Some(LongPress), None, None]
+        );
+    }
+
+    #[test]
+    fn release_before_long_press_restarts_it() {
+        use Event::*;
+        assert_eq!(
+            events(active_high(1, 3, 0), &[1, 1, 0, 1, 1, 1, 1]),
+            [
+                Some(Pressed),
+                None,
+                Some(Released),
+                Some(Pressed),
+                None,
+                None,
+                Some(LongPress)
+            ]
+        );
+    }
+
+    /// Timer which wraps on every other `wait`
+    struct EveryOther(bool);
+
+    impl CountDown for EveryOther {
+        type Time = u32;
+
+        fn start<T>(&mut self, _: T)
+        where
+            T: Into<u32>,
+        {
+        }
+
+        fn wait(&mut self) -> nb::Result<(), Void> {
+            self.0 = !self.0;
+            if self.0 {
+                Ok(())
+            } else {
+                Err(nb::Error::WouldBlock)
+            }
+        }
+    }
+
+    #[test]
+    fn poll_only_samples_when_timer_wraps() {
+        let levels = [1, 1];
+        let mut input = Debounced::new(Recorded::new(&levels), active_high(2, 0, 0));
+        let mut timer = EveryOther(true);
+        assert_eq!(input.poll(&mut timer), Err(nb::Error::WouldBlock));
+        assert_eq!(input.poll(&mut timer), Err(nb::Error::WouldBlock));
+        assert_eq!(input.poll(&mut timer), Err(nb::Error::WouldBlock));
+        assert_eq!(input.poll(&mut timer), Ok(Event::Pressed));
+    }
+}
@@ -0,0 +1,326 @@
+//! Scanner for key matrices
+//!
+//! The rows are driven low one after another, while the columns are read through pull-ups.
+//! A pressed key pulls its column low while its row is selected.
+//!
+//! A matrix can be scanned at once with `scan`, which waits for the lines to settle with a
+//! delay, or one row per call with `poll`, e.g. from a timer tick. Then the lines settle in
+//! between the calls.
+
+use embedded_hal::blocking::delay::DelayUs;
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+
+use crate::gpio::{Input, Output, Pin, PullUp, PushPull};
+
+/// Pressed keys of a matrix
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Keys {
+    bits: u32,
+    cols: u8,
+    ghosting: bool,
+}
+
+impl Keys {
+    /// Returns the pressed keys, bit `row * COLS + col` is set for a pressed key
+    pub fn bits(&self) -> u32 {
+        self.bits
+    }
+
+    /// Returns `true` if the key is pressed
+    pub fn is_pressed(&self, row: usize, col: usize) -> bool {
+        self.bits & (1 << (row * usize::from(self.cols) + col)) != 0
+    }
+
+    /// Returns `true` if no key is pressed
+    pub fn is_empty(&self) -> bool {
+        self.bits == 0
+    }
+
+    /// Returns `true` if the pressed keys are ambiguous
+    ///
+    /// Without diodes, three keys at the corners of a rectangle make the fourth one look
+    /// pressed as well. When this is set, some of the reported keys may not actually be
+    /// pressed.
+    pub fn ghosting(&self) -> bool {
+        self.ghosting
+    }
+}
+
+/// Key matrix with `ROWS` rows and `COLS` columns
+///
+/// At most 32 keys are supported.
+pub struct Matrix<
+    const ROWS: usize,
+    const COLS: usize,
+    R = Pin<Output<PushPull>>,
+    C = Pin<Input<PullUp>>,
+> {
+    rows: [R; ROWS],
+    cols: [C; COLS],
+    settle_us: u32,
+    row: usize,
+    bits: u32,
+}
+
+impl<const ROWS: usize, const COLS: usize, R, C, E> Matrix<ROWS, COLS, R, C>
+where
+    R: OutputPin<Error = E>,
+    C: InputPin<Error = E>,
+{
+    /// Creates a matrix, `settle_us` is the time the lines need to settle after changing rows
+    pub fn new(rows: [R; ROWS], cols: [C; COLS], settle_us: u32) -> Result<Self, E> {
+        assert!(ROWS != 0 && COLS != 0 && ROWS * COLS <= 32);
+        let mut matrix = Matrix {
+            rows,
+            cols,
+            settle_us,
+            row: 0,
+            bits: 0,
+        };
+        for row in matrix.rows.iter_mut().skip(1) {
+            row.set_high()?;
+        }
+        // Select the first row for `poll`
+        matrix.rows[0].set_low()?;
+        Ok(matrix)
+    }
+
+    /// Reads the columns of the currently selected row
+    fn read_row(&self) -> Result<u32, E> {
+        let mut bits = 0;
+        for (col, pin) in self.cols.iter().enumerate() {
+            if pin.is_low()? {
+                bits |= 1 << col;
+            }
+        }
+        Ok(bits << (self.row * COLS))
+    }
+
+    /// Scans the whole matrix, waiting `settle_us` after selecting each row
+    pub fn scan<D>(&mut self, delay: &mut D) -> Result<Keys, E>
+    where
+        D: DelayUs<u32>,
+    {
+        self.rows[self.row].set_high()?;
+        let mut bits = 0;
+        for row in 0..ROWS {
+            self.row = row;
+            self.rows[row].set_low()?;
+            delay.delay_us(self.settle_us);
+            bits |= self.read_row()?;
+            self.rows[row].set_high()?;
+        }
+        self.row = 0;
+        self.bits = 0;
+        self.rows[0].set_low()?;
+        Ok(self.keys(bits))
+    }
+
+    /// Reads the selected row and selects the next one
+    ///
+    /// Returns the pressed keys once all rows have been read. Calls have to be at least
+    /// `settle_us` apart.
+    pub fn poll(&mut self) -> Result<Option<Keys>, E> {
+        self.bits |= self.read_row()?;
+        self.rows[self.row].set_high()?;
+        self.row += 1;
+        let keys = if self.row == ROWS {
+            self.row = 0;
+            let bits = self.bits;
+            self.bits = 0;
+            Some(self.keys(bits))
+        } else {
+            None
+        };
+        self.rows[self.row].set_low()?;
+        Ok(keys)
+    }
+
+    fn keys(&self, bits: u32) -> Keys {
+        Keys {
+            bits,
+            cols: COLS as u8,
+            ghosting: ghosting(bits, ROWS, COLS),
+        }
+    }
+
+    /// Releases the pins
+    pub fn release(self) -> ([R; ROWS], [C; COLS]) {
+        (self.rows, self.cols)
+    }
+}
+
+/// Returns `true` if two rows share at least two pressed columns
+fn ghosting(bits: u32, rows: usize, cols: usize) -> bool {
+    let mask = u32::MAX >> (32 - cols);
+    let row = |r: usize| (bits >> (r * cols)) & mask;
+    (0..rows).any(|a| ((a + 1)..rows).any(|b| (row(a) & row(b)).count_ones() >= 2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::Cell;
+
+    const ROWS: usize = 3;
+    const COLS: usize = 3;
+
+    /// Key matrix without diodes
+    struct Board {
+        /// Bit `row * COLS + col` is set for a pressed key
+        keys: Cell<u32>,
+        /// Bit `row` is set for a row driven low
+        low_rows: Cell<u32>,
+        delay_us: Cell<u32>,
+    }
+
+    impl Board {
+        fn new(keys: &[(usize, usize)]) -> Self {
+            Board {
+                keys: Cell::new(
+                    keys.iter()
+                        .fold(0, |bits, &(r, c)| bits | 1 << (r * COLS + c)),
+                ),
+                low_rows: Cell::new(0),
+                delay_us: Cell::new(0),
+            }
+        }
+
+        fn key(&self, row: usize, col: usize) -> bool {
+            self.keys.get() & (1 << (row * COLS + col)) != 0
+        }
+
+        /// A column is low if pressed keys connect it to a low row, possibly through other rows
+        /// and columns
+        fn is_col_low(&self, col: usize) -> bool {
+            let mut rows = self.low_rows.get();
+            let mut cols = 0;
+            loop {
+                let reached_cols = (0..COLS)
+                    .filter(|&c| (0..ROWS).any(|r| rows & (1 << r) != 0 && self.key(r, c)))
+                    .fold(0, |bits, c| bits | 1 << c);
+                let reached_rows = (0..ROWS)
+                    .filter(|&r| (0..COLS).any(|c| reached_cols & (1 << c) != 0 && self.key(r, c)))
+                    .fold(rows, |bits, r| bits | 1 << r);
+                if reached_cols == cols && reached_rows == rows {
+                    return cols & (1 << col) != 0;
+                }
+                cols = reached_cols;
+                rows = reached_rows;
+            }
+        }
+    }
+
+    struct RowPin<'a> {
+        board: &'a Board,
+        row: usize,
+    }
+
+    impl OutputPin for RowPin<'_> {
+        type Error = ();
+
+        fn set_low(&mut self) -> Result<(), ()> {
+            let low_rows = self.board.low_rows.get();
+            self.board.low_rows.set(low_rows | 1 << self.row);
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), ()> {
+            let low_rows = self.board.low_rows.get();
+            self.board.low_rows.set(low_rows & !(1 << self.row));
+            Ok(())
+        }
+    }
+
+    struct ColPin<'a> {
+        board: &'a Board,
+        col: usize,
+    }
+
+    impl InputPin for ColPin<'_> {
+        type Error = ();
+
+        fn is_high(&self) -> Result<bool, ()> {
+            self.is_low().map(|low| !low)
+        }
+
+        fn is_low(&self) -> Result<bool, ()> {
+            Ok(self.board.is_col_low(self.col))
+        }
+    }
+
+    impl DelayUs<u32> for &Board {
+        fn delay_us(&mut self, us: u32) {
+            self.delay_us.set(self.delay_us.get() + us);
+        }
+    }
+
+    fn matrix(board: &Board) -> Matrix<ROWS, COLS, RowPin<'_>, ColPin<'_>> {
+        let rows = [0, 1, 2].map(|row| RowPin { board, row });
+        let cols = [0, 1, 2].map(|col| ColPin { board, col });
+        Matrix::new(rows, cols, 10).unwrap()
+    }
+
+    #[test]
+    fn scan_reports_pressed_keys() {
+        let board = Board::new(&[(1, 2)]);
+        let mut matrix = matrix(&board);
+
+        let keys = matrix.scan(&mut &board).unwrap();
+        assert_eq!(keys.bits(), 1 << 5);
+        assert!(keys.is_pressed(1, 2));
+        assert!(!keys.is_pressed(2, 1));
+        assert!(!keys.ghosting());
+        // Every row waits for the lines to settle
+        assert_eq!(board.delay_us.get(), 30);
+        // Only the first row stays selected, for `poll`
+        assert_eq!(board.low_rows.get(), 0b001);
+
+        board.keys.set(0);
+        assert!(matrix.scan(&mut &board).unwrap().is_empty());
+    }
+
+    #[test]
+    fn keys_not_forming_a_rectangle_are_not_ghosting() {
+        let board = Board::new(&[(0, 0), (0, 1), (2, 2)]);
+        let keys = matrix(&board).scan(&mut &board).unwrap();
+        assert_eq!(keys.bits(), 0b100_000_011);
+        assert!(!keys.ghosting());
+    }
+
+    #[test]
+    fn three_keys_in_an_l_shape_are_ghosting() {
+        let board = Board::new(&[(0, 0), (0, 1), (1, 0)]);
+        let keys = matrix(&board).scan(&mut &board).unwrap();
+        // The fourth corner looks pressed as well
+        assert!(keys.is_pressed(1, 1));
+        assert_eq!(keys.bits(), 0b000_011_011);
+        assert!(keys.ghosting());
+    }
+
+    #[test]
+    fn poll_reads_one_row_per_call() {
+        let board = Board::new(&[(0, 1), (2, 0)]);
+        let mut matrix = matrix(&board);
+        assert_eq!(board.low_rows.get(), 0b001);
+
+        for _ in 0..2 {
+            assert_eq!(matrix.poll(), Ok(None));
+            assert_eq!(board.low_rows.get(), 0b010);
+            assert_eq!(matrix.poll(), Ok(None));
+            assert_eq!(board.low_rows.get(), 0b100);
+            let keys = matrix.poll().unwrap().unwrap();
+            assert_eq!(board.low_rows.get(), 0b001);
+            assert_eq!(keys.bits(), 0b001_000_010);
+            assert!(!keys.ghosting());
+        }
+        assert_eq!(board.delay_us.get(), 0);
+    }
+
+    #[test]
+    fn ghosting_handles_full_width_rows() {
+        assert!(!ghosting(u32::MAX, 1, 32));
+        assert!(ghosting(0b0101_0101, 2, 4));
+        assert!(!ghosting(0b0101_1010, 2, 4));
+    }
+}
@@ -1,12 +1,17 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 #![allow(non_camel_case_types)]
 
 pub use swm050;
 
+pub mod charlieplex;
 pub mod delay;
+pub mod encoder;
 pub mod gpio;
+pub mod keypad;
+pub mod power;
 pub mod prelude;
 pub mod pwm;
+pub mod seven_segment;
 pub mod syscon;
 pub mod time;
 pub mod timers;
@@ -0,0 +1,56 @@
+//! Low power modes
+//!
+//! The core sleeps until an interrupt is pending, so GPIOA pins armed with `arm_wakeup` wake it
+//! up. This also works with interrupts disabled, e.g. in `interrupt::free`. Then the interrupt
+//! handler only runs once they're enabled again, and the pending bits of the pins have to be
+//! cleared by hand before going back to sleep.
+
+use cortex_m::interrupt::CriticalSection;
+use cortex_m::peripheral::SCB;
+
+use crate::gpio::{gpioa, InterruptPin, Trigger};
+use crate::swm050::NVIC;
+use crate::syscon::Syscon;
+
+/// Sleeps until an interrupt is pending
+///
+/// All clocks keep running
+pub fn sleep(scb: &mut SCB) {
+    scb.clear_sleepdeep();
+    cortex_m::asm::wfi();
+}
+
+/// Sleeps in deep sleep mode until an interrupt is pending
+///
+/// This only sets `SLEEPDEEP` of the core, the `SYS` block has no low power settings (SWM050
+/// user manual, `SYS` register map). The clock configuration of `syscon` is restored afterwards.
+/// This runs in a critical section, so the interrupt handler which woke up the core only runs
+/// once the clocks are back.
+pub fn deep_sleep(scb: &mut SCB, syscon: &mut Syscon, _cs: &CriticalSection) {
+    scb.set_sleepdeep();
+    // Make sure all memory accesses are done before going to sleep
+    cortex_m::asm::dsb();
+    cortex_m::asm::wfi();
+    scb.clear_sleepdeep();
+    syscon.restore_clocks();
+}
+
+/// Arms `pin` as wake up source, which wakes up the core on `trigger`
+///
+/// This enables the pin-change interrupt of the pin and unmasks the GPIOA interrupt
+pub fn arm_wakeup<P>(pin: &mut P, trigger: Trigger, nvic: &mut NVIC, cs: &CriticalSection)
+where
+    P: InterruptPin,
+{
+    pin.clear_interrupt_pending_bit();
+    pin.listen(trigger, cs);
+    gpioa::unmask_interrupt(nvic);
+}
+
+/// Removes `pin` from the wake up sources
+pub fn disarm_wakeup<P>(pin: &mut P, cs: &CriticalSection)
+where
+    P: InterruptPin,
+{
+    pin.unlisten(cs);
+}
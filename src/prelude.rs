@@ -1,4 +1,6 @@
 pub use crate::gpio::GpioExt as _swm050_hal_gpio_GpioExt;
+pub use crate::gpio::InterruptPin as _swm050_hal_gpio_InterruptPin;
+pub use crate::gpio::PinArrayExt as _swm050_hal_gpio_PinArrayExt;
 pub use crate::syscon::SysconExt as _swm050_hal_syscon_SysconExt;
 
 pub use embedded_hal::digital::v2::InputPin as _embedded_hal_gpio_InputPin;
@@ -1,55 +1,96 @@
 use core::ops::Deref;
 
 use embedded_hal::PwmPin;
-use swm050::{PORT, TMRSE1};
+use swm050::{TMRSE0, TMRSE1};
 
 use crate::gpio;
-use crate::gpio::Output;
-use crate::syscon::{ClockEnable, Syscon};
+use crate::gpio::{Alternate, AF2};
+use crate::syscon::{ticks, ClockEnable, ClockError, Clocks, Syscon};
 use crate::time::Hertz;
+use crate::timers;
 use crate::timers::TimerRegisterBlock;
 
 pub struct Pwm<TIMER, PIN> {
     timer: TIMER,
     pin: PIN,
+    period: Hertz,
     ticks: u16,
 }
 
-// TODO TMRSE0 isn't supported yet, since it shares pins with swd
-// Not sure how to do this while ensuring that swd isn't accidentally disabled
-impl Pwm<TMRSE1, gpio::gpioa::PA_7<Output>> {
-    pub fn new<T>(
-        timer: TMRSE1,
-        pin: gpio::gpioa::PA_7<Output>,
-        period: T,
-        port: &mut PORT,
-        syscon: &mut Syscon,
-    ) -> Self
-    where
-        T: Into<Hertz>,
-    {
-        TMRSE1::enable(syscon);
-
-        let frequency = period.into().0;
-        let ticks = syscon.clocks.timsclk().0 / frequency;
-        assert!(ticks < 0x10000);
-
-        // Enable output
-        port.porta_sel.modify(|_, w| w.pa07().tmrse1_out());
-        timer.ctrl.write(|w| w.ena().set_bit().wmod().pwm());
-        let mut pwm_pin = Pwm {
-            timer: timer,
-            pin: pin,
-            ticks: ticks as u16,
-        };
-        pwm_pin.set_duty(0);
-        pwm_pin
-    }
+macro_rules! pwm {
+    ($($TIMER:ident: $PIN:ty,)+) => {
+        $(
+            impl Pwm<$TIMER, $PIN> {
+                pub fn new<T>(timer: $TIMER, pin: $PIN, period: T, syscon: &mut Syscon) -> Self
+                where
+                    T: Into<Hertz>,
+                {
+                    Self::try_new(timer, pin, period, syscon)
+                        .map_err(|(e, _, _)| e)
+                        .unwrap()
+                }
+
+                /// Fails if the period doesn't fit into 16 bits of timer ticks
+                ///
+                /// The timer and pin are returned untouched on error
+                pub fn try_new<T>(
+                    timer: $TIMER,
+                    pin: $PIN,
+                    period: T,
+                    syscon: &mut Syscon,
+                ) -> Result<Self, (ClockError, $TIMER, $PIN)>
+                where
+                    T: Into<Hertz>,
+                {
+                    let period = period.into();
+                    let ticks = match ticks(syscon.clocks.timsclk(), period, 0xFFFF) {
+                        Ok(ticks) => ticks,
+                        Err(e) => return Err((e, timer, pin)),
+                    };
+                    $TIMER::enable(syscon);
+
+                    timer.ctrl.write(|w| w.ena().set_bit().wmod().pwm());
+                    let mut pwm_pin = Pwm {
+                        timer,
+                        pin,
+                        period,
+                        ticks: ticks as u16,
+                    };
+                    pwm_pin.set_duty(0);
+                    Ok(pwm_pin)
+                }
+
+                /// Stops the timer and gates its clock
+                pub fn release(self, syscon: &mut Syscon) -> ($TIMER, $PIN) {
+                    timers::reset(&self.timer);
+                    $TIMER::disable(syscon);
+                    (self.timer, self.pin)
+                }
+            }
+        )+
+    };
 }
-impl Pwm<TMRSE1, gpio::gpioa::PA_7<Output>> {
-    pub fn release(self, port: &mut PORT) -> (TMRSE1, gpio::gpioa::PA_7<Output>) {
-        port.porta_sel.modify(|_, w| w.pa07().gpio());
-        (self.timer, self.pin)
+
+// TMRSE0 shares its output with SWD, so the pin has to be released through `gpioa::SwdPins`
+pwm!(
+    TMRSE0: gpio::gpioa::PA_2<Alternate<AF2>>,
+    TMRSE1: gpio::gpioa::PA_7<Alternate<AF2>>,
+);
+
+impl<TIMER, PIN> Pwm<TIMER, PIN>
+where
+    TIMER: Deref<Target = TimerRegisterBlock>,
+{
+    /// Recalculates the period after the clocks were changed with `Syscon::reconfigure`
+    ///
+    /// The duty cycle is scaled to the new maximum. Fails if the period doesn't fit into
+    /// 16 bits of timer ticks anymore, in which case nothing changes.
+    pub fn refresh(&mut self, clocks: Clocks) -> Result<(), ClockError> {
+        let ticks = ticks(clocks.timsclk(), self.period, 0xFFFF)? as u16;
+        let duty = u32::from(self.get_duty()) * u32::from(ticks) / u32::from(self.ticks);
+        self.ticks = ticks;
+        self.set_duty(duty as u16);
+        Ok(())
     }
 }
 
@@ -79,10 +120,32 @@ where
         self.ticks
     }
 
+    /// Sets the high time, values above `get_max_duty` are clamped
     fn set_duty(&mut self, duty: Self::Duty) {
-        let low = duty - self.get_max_duty();
+        let duty = duty.min(self.get_max_duty());
+        let low = self.get_max_duty() - duty;
         self.timer
             .tarval
             .write(|w| unsafe { w.bits(duty as u32 | ((low as u32) << 16)) });
     }
 }
+
+#[cfg(feature = "eh1")]
+impl<TIMER, GPIO> embedded_hal_1::pwm::ErrorType for Pwm<TIMER, GPIO> {
+    type Error = core::convert::Infallible;
+}
+
+#[cfg(feature = "eh1")]
+impl<TIMER, GPIO> embedded_hal_1::pwm::SetDutyCycle for Pwm<TIMER, GPIO>
+where
+    TIMER: Deref<Target = TimerRegisterBlock>,
+{
+    fn max_duty_cycle(&self) -> u16 {
+        self.ticks
+    }
+
+    fn set_duty_cycle(&mut self, duty: u16) -> Result<(), core::convert::Infallible> {
+        PwmPin::set_duty(self, duty);
+        Ok(())
+    }
+}
@@ -0,0 +1,450 @@
+//! Multiplexed 7-segment displays
+//!
+//! The segment lines of all digits are connected together and each digit has its own select
+//! line, so only one digit is lit at a time. `Display::tick` has to be called periodically,
+//! e.g. from a `timers::Timer` interrupt, to cycle through the digits. Each digit is shown for
+//! `STEPS` ticks, of which it's lit for the set brightness.
+//!
+//! Segments are encoded as bits `0..=6` for the segments `a` to `g` and bit 7 for the decimal
+//! point. Digit 0 is the leftmost digit.
+
+use embedded_hal::digital::v2::OutputPin;
+
+use crate::gpio::PortBus;
+
+/// Decimal point
+pub const DP: u8 = 1 << 7;
+/// Minus sign, segment `g`
+pub const MINUS: u8 = 1 << 6;
+/// No segments
+pub const BLANK: u8 = 0;
+
+/// Segments of the hexadecimal digits `0` to `F`
+pub const FONT: [u8; 16] = [
+    0x3F, // 0
+    0x06, // 1
+    0x5B, // 2
+    0x4F, // 3
+    0x66, // 4
+    0x6D, // 5
+    0x7D, // 6
+    0x07, // 7
+    0x7F, // 8
+    0x6F, // 9
+    0x77, // A
+    0x7C, // b
+    0x39, // C
+    0x5E, // d
+    0x79, // E
+    0x71, // F
+];
+
+/// Number of ticks each digit is shown for
+pub const STEPS: u8 = 8;
+
+/// Returns the segments for `c`, if it can be displayed
+pub fn encode(c: char) -> Option<u8> {
+    match c {
+        '0'..='9' | 'a'..='f' | 'A'..='F' => c.to_digit(16).map(|d| FONT[d as usize]),
+        '-' => Some(MINUS),
+        ' ' => Some(BLANK),
+        '_' => Some(0x08),
+        'H' | 'h' => Some(0x76),
+        'L' | 'l' => Some(0x38),
+        'P' | 'p' => Some(0x73),
+        'o' => Some(0x5C),
+        'r' => Some(0x50),
+        'u' => Some(0x1C),
+        _ => None,
+    }
+}
+
+/// The value doesn't fit on the display
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Overflow;
+
+/// Wiring of the display
+#[derive(Clone, Copy, PartialEq)]
+pub enum Polarity {
+    /// Segments are active high, digit selects active low
+    CommonCathode,
+    /// Segments are active low, digit selects active high
+    CommonAnode,
+}
+
+/// Outputs driving the segment lines
+pub trait Segments {
+    type Error;
+
+    /// Drives the segment lines, bit `n` of `levels` goes to segment `n`
+    fn write(&mut self, levels: u8) -> Result<(), Self::Error>;
+}
+
+impl<const N: usize> Segments for PortBus<N> {
+    type Error = ();
+
+    fn write(&mut self, levels: u8) -> Result<(), ()> {
+        PortBus::write(self, u32::from(levels));
+        Ok(())
+    }
+}
+
+impl<P, const N: usize> Segments for [P; N]
+where
+    P: OutputPin,
+{
+    type Error = P::Error;
+
+    fn write(&mut self, levels: u8) -> Result<(), P::Error> {
+        for (n, pin) in self.iter_mut().enumerate() {
+            if levels & (1 << n) != 0 {
+                pin.set_high()?;
+            } else {
+                pin.set_low()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Multiplexed display with `DIGITS` digits
+pub struct Display<S, D, const DIGITS: usize> {
+    segments: S,
+    digits: [D; DIGITS],
+    polarity: Polarity,
+    buffer: [u8; DIGITS],
+    brightness: u8,
+    blank_zeros: bool,
+    digit: usize,
+    step: u8,
+}
+
+impl<S, D, E, const DIGITS: usize> Display<S, D, DIGITS>
+where
+    S: Segments<Error = E>,
+    D: OutputPin<Error = E>,
+{
+    /// Creates a blank display at full brightness with leading zero blanking
+    pub fn new(segments: S, digits: [D; DIGITS], polarity: Polarity) -> Result<Self, E> {
+        assert!(DIGITS != 0);
+        let mut display = Display {
+            segments,
+            digits,
+            polarity,
+            buffer: [BLANK; DIGITS],
+            brightness: STEPS,
+            blank_zeros: true,
+            // Start at the end of the last digit, so the first tick shows the first digit
+            digit: DIGITS - 1,
+            step: STEPS - 1,
+        };
+        for digit in 0..DIGITS {
+            display.select(digit, false)?;
+        }
+        Ok(display)
+    }
+
+    fn select(&mut self, digit: usize, on: bool) -> Result<(), E> {
+        if on == (self.polarity == Polarity::CommonAnode) {
+            self.digits[digit].set_high()
+        } else {
+            self.digits[digit].set_low()
+        }
+    }
+
+    /// Sets the brightness from 0 (off) to `STEPS` (fully on)
+    pub fn set_brightness(&mut self, brightness: u8) {
+        self.brightness = brightness.min(STEPS);
+    }
+
+    /// Enables or disables leading zero blanking for `set_number` and `set_hex`
+    pub fn set_leading_zero_blanking(&mut self, enabled: bool) {
+        self.blank_zeros = enabled;
+    }
+
+    /// Sets the raw segments of a digit
+    pub fn set_segments(&mut self, digit: usize, segments: u8) {
+        self.buffer[digit] = segments;
+    }
+
+    /// Returns the raw segments of a digit
+    pub fn segments(&self, digit: usize) -> u8 {
+        self.buffer[digit]
+    }
+
+    /// Sets or clears the decimal point of a digit
+    pub fn set_dp(&mut self, digit: usize, on: bool) {
+        if on {
+            self.buffer[digit] |= DP;
+        } else {
+            self.buffer[digit] &= !DP;
+        }
+    }
+
+    /// Blanks all digits
+    pub fn clear(&mut self) {
+        self.buffer = [BLANK; DIGITS];
+    }
+
+    /// Shows `value` right aligned in decimal, keeping the decimal points
+    ///
+    /// Leaves the display unchanged if the value doesn't fit
+    pub fn set_number(&mut self, value: i32) -> Result<(), Overflow> {
+        let digits = self.format(value.unsigned_abs(), 10, value < 0)?;
+        self.show(digits);
+        Ok(())
+    }
+
+    /// Shows `value` right aligned in hexadecimal, keeping the decimal points
+    ///
+    /// Leaves the display unchanged if the value doesn't fit
+    pub fn set_hex(&mut self, value: u32) -> Result<(), Overflow> {
+        let digits = self.format(value, 16, false)?;
+        self.show(digits);
+        Ok(())
+    }
+
+    fn format(&self, mut value: u32, radix: u32, negative: bool) -> Result<[u8; DIGITS], Overflow> {
+        let mut digits = [BLANK; DIGITS];
+        // The leftmost digit is kept for the sign, so zeros are only padded up to it
+        let first = usize::from(negative);
+        let mut pos = DIGITS;
+        loop {
+            if pos == first {
+                return Err(Overflow);
+            }
+            pos -= 1;
+            digits[pos] = FONT[(value % radix) as usize];
+            value /= radix;
+            if value == 0 && (self.blank_zeros || pos == first) {
+                break;
+            }
+        }
+        if negative {
+            pos -= 1;
+            digits[pos] = MINUS;
+        }
+        Ok(digits)
+    }
+
+    fn show(&mut self, digits: [u8; DIGITS]) {
+        for (segments, digit) in self.buffer.iter_mut().zip(digits.iter()) {
+            *segments = (*segments & DP) | digit;
+        }
+    }
+
+    /// Advances the multiplexing by one tick
+    pub fn tick(&mut self) -> Result<(), E> {
+        self.step += 1;
+        if self.step < STEPS {
+            if self.step == self.brightness {
+                self.select(self.digit, false)?;
+            }
+        } else {
+            self.step = 0;
+            self.select(self.digit, false)?;
+            self.digit = (self.digit + 1) % DIGITS;
+            let segments = match self.polarity {
+                Polarity::CommonCathode => self.buffer[self.digit],
+                Polarity::CommonAnode => !self.buffer[self.digit],
+            };
+            self.segments.write(segments)?;
+            if self.brightness != 0 {
+                self.select(self.digit, true)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Switches all digits off and releases the pins
+    pub fn release(mut self) -> Result<(S, [D; DIGITS]), E> {
+        for digit in 0..DIGITS {
+            self.select(digit, false)?;
+        }
+        Ok((self.segments, self.digits))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::RefCell;
+
+    #[derive(Clone, Copy, PartialEq, Debug)]
+    enum Op {
+        Segments(u8),
+        Digit(usize, bool),
+    }
+
+    /// Segment lines and digit selects recording into a shared log
+    struct Lines<'a>(&'a RefCell<Vec<Op>>);
+
+    struct Select<'a> {
+        log: &'a RefCell<Vec<Op>>,
+        i: usize,
+    }
+
+    impl Segments for Lines<'_> {
+        type Error = ();
+
+        fn write(&mut self, levels: u8) -> Result<(), ()> {
+            self.0.borrow_mut().push(Op::Segments(levels));
+            Ok(())
+        }
+    }
+
+    impl OutputPin for Select<'_> {
+        type Error = ();
+
+        fn set_low(&mut self) -> Result<(), ()> {
+            self.log.borrow_mut().push(Op::Digit(self.i, false));
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), ()> {
+            self.log.borrow_mut().push(Op::Digit(self.i, true));
+            Ok(())
+        }
+    }
+
+    fn display(log: &RefCell<Vec<Op>>, polarity: Polarity) -> Display<Lines<'_>, Select<'_>, 4> {
+        let digits = [0, 1, 2, 3].map(|i| Select { log, i });
+        let display = Display::new(Lines(log), digits, polarity).unwrap();
+        log.borrow_mut().clear();
+        display
+    }
+
+    fn buffer(display: &Display<Lines, Select, 4>) -> [u8; 4] {
+        [0, 1, 2, 3].map(|digit| display.segments(digit))
+    }
+
+    #[test]
+    fn encode_uses_the_font() {
+        for (n, c) in "0123456789abcdef".chars().enumerate() {
+            assert_eq!(encode(c), Some(FONT[n]));
+            assert_eq!(encode(c.to_ascii_uppercase()), Some(FONT[n]));
+        }
+        assert_eq!(FONT[1], 0b0000_0110);
+        assert_eq!(FONT[8], 0b0111_1111);
+        assert_eq!(encode('-'), Some(MINUS));
+        assert_eq!(encode(' '), Some(BLANK));
+        assert_eq!(encode('H'), Some(0x76));
+        assert_eq!(encode('x'), None);
+        assert_eq!(encode('.'), None);
+    }
+
+    #[test]
+    fn numbers_with_leading_zero_blanking() {
+        let log = RefCell::new(Vec::new());
+        let mut display = display(&log, Polarity::CommonCathode);
+
+        display.set_number(0).unwrap();
+        assert_eq!(buffer(&display), [BLANK, BLANK, BLANK, FONT[0]]);
+        display.set_number(-5).unwrap();
+        assert_eq!(buffer(&display), [BLANK, BLANK, MINUS, FONT[5]]);
+        display.set_number(1234).unwrap();
+        assert_eq!(buffer(&display), [FONT[1], FONT[2], FONT[3], FONT[4]]);
+        display.set_number(-999).unwrap();
+        assert_eq!(buffer(&display), [MINUS, FONT[9], FONT[9], FONT[9]]);
+        display.set_hex(0xbeef).unwrap();
+        assert_eq!(buffer(&display), [FONT[11], FONT[14], FONT[14], FONT[15]]);
+
+        // Overflows leave the display unchanged
+        assert_eq!(display.set_number(-1000), Err(Overflow));
+        assert_eq!(display.set_number(10000), Err(Overflow));
+        assert_eq!(display.set_hex(0x10000), Err(Overflow));
+        assert_eq!(buffer(&display), [FONT[11], FONT[14], FONT[14], FONT[15]]);
+    }
+
+    #[test]
+    fn numbers_without_leading_zero_blanking() {
+        let log = RefCell::new(Vec::new());
+        let mut display = display(&log, Polarity::CommonCathode);
+        display.set_leading_zero_blanking(false);
+
+        display.set_number(0).unwrap();
+        assert_eq!(buffer(&display), [FONT[0]; 4]);
+        display.set_number(42).unwrap();
+        assert_eq!(buffer(&display), [FONT[0], FONT[0], FONT[4], FONT[2]]);
+        display.set_number(-5).unwrap();
+        assert_eq!(buffer(&display), [MINUS, FONT[0], FONT[0], FONT[5]]);
+        display.set_number(-999).unwrap();
+        assert_eq!(buffer(&display), [MINUS, FONT[9], FONT[9], FONT[9]]);
+        assert_eq!(display.set_number(-1000), Err(Overflow));
+    }
+
+    #[test]
+    fn numbers_keep_decimal_points() {
+        let log = RefCell::new(Vec::new());
+        let mut display = display(&log, Polarity::CommonCathode);
+        display.set_dp(1, true);
+        display.set_number(7).unwrap();
+        assert_eq!(buffer(&display), [BLANK, DP, BLANK, FONT[7]]);
+        display.set_dp(1, false);
+        assert_eq!(display.segments(1), BLANK);
+    }
+
+    #[test]
+    fn tick_scans_digits_in_order() {
+        let log = RefCell::new(Vec::new());
+        let mut display = display(&log, Polarity::CommonCathode);
+        display.set_number(1234).unwrap();
+
+        // Common cathode selects are active low
+        for n in 0..8 {
+            let (previous, digit) = ((n + 3) % 4, n % 4);
+            display.tick().unwrap();
+            assert_eq!(
+                log.replace(Vec::new()),
+                [
+                    Op::Digit(previous, true),
+                    Op::Segments(FONT[digit + 1]),
+                    Op::Digit(digit, false),
+                ]
+            );
+            for _ in 0..STEPS - 1 {
+                display.tick().unwrap();
+            }
+            assert!(log.borrow().is_empty());
+        }
+    }
+
+    #[test]
+    fn common_anode_inverts_the_levels() {
+        let log = RefCell::new(Vec::new());
+        let mut display = display(&log, Polarity::CommonAnode);
+        display.set_segments(0, FONT[8] | DP);
+        display.tick().unwrap();
+        assert_eq!(
+            log.replace(Vec::new()),
+            [Op::Digit(3, false), Op::Segments(0), Op::Digit(0, true)]
+        );
+    }
+
+    #[test]
+    fn brightness_switches_the_digit_off_early() {
+        let log = RefCell::new(Vec::new());
+        let mut display = display(&log, Polarity::CommonCathode);
+        display.set_brightness(3);
+        for _ in 0..=STEPS {
+            display.tick().unwrap();
+        }
+        log.borrow_mut().clear();
+
+        for step in 1..=STEPS {
+            display.tick().unwrap();
+            let ops = log.replace(Vec::new());
+            match step {
+                3 => assert_eq!(ops, [Op::Digit(1, true)]),
+                STEPS => assert_eq!(ops[2], Op::Digit(2, false)),
+                _ => assert!(ops.is_empty()),
+            }
+        }
+
+        display.set_brightness(0);
+        for _ in 0..STEPS {
+            display.tick().unwrap();
+        }
+        assert!(!log.borrow().contains(&Op::Digit(3, false)));
+    }
+}
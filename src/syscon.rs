@@ -1,10 +1,23 @@
 use crate::swm050::SYS;
 use crate::time::Hertz;
 
+/// Frequency of the internal oscillator
+const HSI: u32 = 18_000_000;
+/// Largest value of the clock dividers
+///
+/// The `DIV` fields of `CLK_DIV` and `TMRCLK_DIV` are 8 bits wide
+const MAX_DIV: u32 = 0xFF;
+
 /// Extension trait that sets up the `SYSCON` peripheral
 pub trait SysconExt {
     /// Configure the clocks of the SYSCON peripheral
     fn configure(self) -> CFGR;
+
+    /// Returns the cause of the last reset
+    fn reset_cause(&self) -> ResetCause;
+
+    /// Clears the reset status, so the next reset cause can be told apart
+    fn clear_reset_cause(&mut self);
 }
 
 impl SysconExt for SYS {
@@ -15,6 +28,57 @@ impl SysconExt for SYS {
             syscon: self,
         }
     }
+
+    fn reset_cause(&self) -> ResetCause {
+        let r = self.rst_stat.read();
+        // A power on reset also sets the other flags, so it takes precedence
+        if r.por().bit_is_set() {
+            ResetCause::PowerOn
+        } else if r.wdt().bit_is_set() {
+            ResetCause::Watchdog
+        } else if r.sw().bit_is_set() {
+            ResetCause::Software
+        } else if r.lockup().bit_is_set() {
+            ResetCause::Lockup
+        } else if r.pin().bit_is_set() {
+            ResetCause::Pin
+        } else {
+            ResetCause::Unknown
+        }
+    }
+
+    fn clear_reset_cause(&mut self) {
+        // The flags are cleared by writing ones
+        self.rst_stat.write(|w| {
+            w.por()
+                .set_bit()
+                .wdt()
+                .set_bit()
+                .sw()
+                .set_bit()
+                .lockup()
+                .set_bit()
+                .pin()
+                .set_bit()
+        });
+    }
+}
+
+/// Cause of the last reset
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ResetCause {
+    /// The supply was switched on or dropped below the brown out level
+    PowerOn,
+    /// The external reset pin was pulled low
+    Pin,
+    /// The watchdog timed out
+    Watchdog,
+    /// The firmware requested a reset, e.g. with `SCB::sys_reset`
+    Software,
+    /// The core locked up, e.g. after a fault in the hard fault handler
+    Lockup,
+    /// No reset flag is set, e.g. because they were cleared since the last reset
+    Unknown,
 }
 
 /// Constrained syscon peripheral
@@ -23,6 +87,86 @@ pub struct Syscon {
     pub(crate) regs: SYS,
 }
 
+impl Syscon {
+    /// Switches to the clock configuration closest to the requested frequencies
+    ///
+    /// Peripherals which were set up with the old clocks keep their tick values, so
+    /// `refresh` has to be called on every `Timer`, `Delay`, `Pwm` and `Watchdog` in use
+    /// afterwards.
+    pub fn reconfigure<F, G>(&mut self, sclk: F, timsclk: G) -> Clocks
+    where
+        F: Into<Hertz>,
+        G: Into<Hertz>,
+    {
+        self.clocks = closest_clocks(sclk.into().0, timsclk.into().0);
+        apply(&self.regs, &self.clocks);
+        self.clocks
+    }
+
+    /// Switches the clock configuration, if the requested frequencies can be reached exactly
+    ///
+    /// The clocks stay untouched on error.
+    pub fn try_reconfigure<F, G>(&mut self, sclk: F, timsclk: G) -> Result<Clocks, ClockError>
+    where
+        F: Into<Hertz>,
+        G: Into<Hertz>,
+    {
+        let (sclk, timsclk) = (sclk.into().0, timsclk.into().0);
+        let clocks = closest_clocks(sclk, timsclk);
+        check_frequency(Some(sclk), clocks.sclk)?;
+        check_frequency(Some(timsclk), clocks.timsclk)?;
+        self.clocks = clocks;
+        apply(&self.regs, &self.clocks);
+        Ok(self.clocks)
+    }
+
+    /// Returns the cause of the last reset
+    pub fn reset_cause(&self) -> ResetCause {
+        self.regs.reset_cause()
+    }
+
+    /// Clears the reset status, so the next reset cause can be told apart
+    pub fn clear_reset_cause(&mut self) {
+        self.regs.clear_reset_cause();
+    }
+
+    /// Reprograms the current clock configuration, e.g. after waking up from deep sleep
+    pub(crate) fn restore_clocks(&mut self) {
+        apply(&self.regs, &self.clocks);
+    }
+}
+
+/// Programs the oscillator and dividers of `clocks`
+fn apply(regs: &SYS, clocks: &Clocks) {
+    // The clocks are the oscillator divided by `DIV`, the field holds the divisor itself rather
+    // than the divisor minus one (SWM050 user manual, `SYS_CLK_DIV` and `SYS_TMRCLK_DIV`).
+    // `DBLF` selects the 36 MHz doubled oscillator as source for both.
+    let set_dividers = || {
+        regs.clk_div.write(|w| w.div().bits(clocks.sclk_div));
+        regs.tmrclk_div.write(|w| w.div().bits(clocks.timsclk_div));
+    };
+    // Only switch the doubler on after the dividers are set up for it and switch it off before
+    // the dividers are lowered, so the clocks never run faster than requested
+    if clocks.doubled {
+        set_dividers();
+        regs.dblf_sel.write(|w| w.dblf().set_bit());
+    } else {
+        regs.dblf_sel.write(|w| w.dblf().clear_bit());
+        set_dividers();
+    }
+}
+
+/// Returns the divider which brings `source` closest to `target`
+fn closest_divider(source: u32, target: u32) -> u32 {
+    let low = (source / target.max(1)).clamp(1, MAX_DIV);
+    let high = (low + 1).min(MAX_DIV);
+    if (source / low).abs_diff(target) <= (source / high).abs_diff(target) {
+        low
+    } else {
+        high
+    }
+}
+
 pub struct CFGR {
     timsclk: Option<u32>,
     sclk: Option<u32>,
@@ -46,26 +190,113 @@ impl CFGR {
         self
     }
 
+    /// Applies the clock configuration
+    ///
+    /// The oscillator runs at 18 MHz, or 36 MHz with the frequency doubler enabled. Both
+    /// `sclk` and `timsclk` are derived from it with an integer divider, so the closest
+    /// achievable frequencies are used. Frequencies which aren't set default to 18 MHz.
+    ///
+    /// If no frequency is set, the registers are left in their reset state, which runs both
+    /// clocks at 18 MHz.
     pub fn freeze(self) -> Syscon {
-        let sclk = self.sclk.map(|_| unimplemented!()).unwrap_or(18000000);
-        let timsclk = self.timsclk.map(|_| unimplemented!()).unwrap_or(18000000);
+        let clocks = closest_clocks(self.sclk.unwrap_or(HSI), self.timsclk.unwrap_or(HSI));
+        self.apply_if_requested(&clocks);
         Syscon {
-            clocks: Clocks {
-                timsclk: Hertz(timsclk),
-                sclk: Hertz(sclk),
-            },
+            clocks,
+            regs: self.syscon,
+        }
+    }
+
+    /// Applies the clock configuration, if the requested frequencies can be reached exactly
+    ///
+    /// Otherwise the closest achievable frequency is returned in the error, together with the
+    /// untouched `SYS` peripheral
+    pub fn try_freeze(self) -> Result<Syscon, (ClockError, SYS)> {
+        let clocks = closest_clocks(self.sclk.unwrap_or(HSI), self.timsclk.unwrap_or(HSI));
+        if let Err(e) = check_frequency(self.sclk, clocks.sclk)
+            .and_then(|_| check_frequency(self.timsclk, clocks.timsclk))
+        {
+            return Err((e, self.syscon));
+        }
+        self.apply_if_requested(&clocks);
+        Ok(Syscon {
+            clocks,
             regs: self.syscon,
+        })
+    }
+
+    fn apply_if_requested(&self, clocks: &Clocks) {
+        if self.sclk.is_some() || self.timsclk.is_some() {
+            apply(&self.syscon, clocks);
         }
     }
 }
 
-/// Frozen clock frequencies
+/// Returns the reachable clock configuration closest to the requested frequencies
+fn closest_clocks(sclk: u32, timsclk: u32) -> Clocks {
+    // Prefer the undoubled oscillator, which uses less power
+    [false, true]
+        .iter()
+        .map(|&doubled| {
+            let source = if doubled { 2 * HSI } else { HSI };
+            let sclk_div = closest_divider(source, sclk);
+            let timsclk_div = closest_divider(source, timsclk);
+            Clocks {
+                sclk: Hertz(source / sclk_div),
+                timsclk: Hertz(source / timsclk_div),
+                doubled,
+                sclk_div: sclk_div as u8,
+                timsclk_div: timsclk_div as u8,
+            }
+        })
+        .min_by_key(|clocks| clocks.sclk.0.abs_diff(sclk) + clocks.timsclk.0.abs_diff(timsclk))
+        .unwrap()
+}
+
+fn check_frequency(requested: Option<u32>, nearest: Hertz) -> Result<(), ClockError> {
+    match requested {
+        Some(requested) if requested != nearest.0 => Err(ClockError::FrequencyUnreachable {
+            requested: Hertz(requested),
+            nearest,
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Clock configuration which the hardware can't meet
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ClockError {
+    /// The requested frequency can't be generated, `nearest` is the closest achievable one
+    FrequencyUnreachable { requested: Hertz, nearest: Hertz },
+    /// The requested period is too long or too short for the peripheral
+    PeriodOutOfRange,
+}
+
+/// Returns the number of `clock` ticks in one period of `frequency`
 ///
-/// The existence of this value indicates that the clock configuration can no longer be changed
+/// Fails if that's less than one tick or more than `max` ticks
+pub(crate) fn ticks(clock: Hertz, frequency: Hertz, max: u32) -> Result<u32, ClockError> {
+    if frequency.0 == 0 {
+        return Err(ClockError::PeriodOutOfRange);
+    }
+    let ticks = clock.0 / frequency.0;
+    if ticks == 0 || ticks > max {
+        Err(ClockError::PeriodOutOfRange)
+    } else {
+        Ok(ticks)
+    }
+}
+
+/// Clock frequencies
+///
+/// This is a snapshot, the clocks can be changed later with `Syscon::reconfigure`
 #[derive(Clone, Copy)]
 pub struct Clocks {
     timsclk: Hertz,
     sclk: Hertz,
+    doubled: bool,
+    sclk_div: u8,
+    timsclk_div: u8,
 }
 
 impl Clocks {
@@ -80,8 +311,13 @@ impl Clocks {
     }
 }
 
+/// Gates the bus clock of a peripheral
+///
+/// The SYSCON can't reset single peripherals, so drivers put the registers back into their
+/// reset state themselves before disabling the clock.
 pub trait ClockEnable {
     fn enable(syscon: &mut Syscon);
+    fn disable(syscon: &mut Syscon);
 }
 macro_rules! clock_enable {
     ($PERIPH: ident, $field:ident) => {
@@ -89,9 +325,114 @@ macro_rules! clock_enable {
             fn enable(syscon: &mut Syscon) {
                 syscon.regs.pclk_en.modify(|_, w| w.$field().set_bit());
             }
+
+            fn disable(syscon: &mut Syscon) {
+                syscon.regs.pclk_en.modify(|_, w| w.$field().clear_bit());
+            }
         }
     };
 }
 clock_enable!(TMRSE0, tmrse0_clk);
 clock_enable!(TMRSE1, tmrse1_clk);
 clock_enable!(WDT, wdt_clk);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn closest_divider_rounds_to_the_nearest_frequency() {
+        assert_eq!(closest_divider(HSI, HSI), 1);
+        assert_eq!(closest_divider(HSI, 1_000_000), 18);
+        // 9 MHz is 2 MHz off, 6 MHz only 1 MHz
+        assert_eq!(closest_divider(HSI, 7_000_000), 3);
+        // 9 MHz is 0.5 MHz off, 6 MHz 2.5 MHz
+        assert_eq!(closest_divider(HSI, 8_500_000), 2);
+        // Ties go to the faster clock
+        assert_eq!(closest_divider(HSI, 7_500_000), 2);
+    }
+
+    #[test]
+    fn closest_divider_stays_in_range() {
+        assert_eq!(closest_divider(HSI, 2 * HSI), 1);
+        assert_eq!(closest_divider(HSI, 0), MAX_DIV);
+        assert_eq!(closest_divider(HSI, 100), MAX_DIV);
+        assert_eq!(closest_divider(HSI, HSI / MAX_DIV), MAX_DIV);
+    }
+
+    fn assert_clocks(clocks: Clocks, sclk: u32, timsclk: u32, doubled: bool) {
+        assert_eq!(clocks.sclk, Hertz(sclk));
+        assert_eq!(clocks.timsclk, Hertz(timsclk));
+        assert_eq!(clocks.doubled, doubled);
+        assert_eq!(
+            u32::from(clocks.sclk_div) * clocks.sclk.0,
+            if doubled { 2 * HSI } else { HSI }
+        );
+    }
+
+    #[test]
+    fn closest_clocks_prefers_the_undoubled_oscillator() {
+        assert_clocks(closest_clocks(HSI, HSI), HSI, HSI, false);
+        // Reachable from both sources
+        assert_clocks(
+            closest_clocks(6_000_000, 9_000_000),
+            6_000_000,
+            9_000_000,
+            false,
+        );
+    }
+
+    #[test]
+    fn closest_clocks_doubles_when_closer() {
+        assert_clocks(
+            closest_clocks(2 * HSI, 12_000_000),
+            2 * HSI,
+            12_000_000,
+            true,
+        );
+        assert_clocks(
+            closest_clocks(12_000_000, 12_000_000),
+            12_000_000,
+            12_000_000,
+            true,
+        );
+        assert_clocks(
+            closest_clocks(48_000_000, 1_000_000),
+            2 * HSI,
+            1_000_000,
+            true,
+        );
+    }
+
+    #[test]
+    fn ticks_checks_the_range() {
+        assert_eq!(ticks(Hertz(HSI), Hertz(1_000_000), u32::MAX), Ok(18));
+        assert_eq!(ticks(Hertz(HSI), Hertz(1_000), 0xFFFF), Ok(18_000));
+        assert_eq!(ticks(Hertz(HSI), Hertz(HSI), 1), Ok(1));
+        assert_eq!(
+            ticks(Hertz(HSI), Hertz(100), 0xFFFF),
+            Err(ClockError::PeriodOutOfRange)
+        );
+        assert_eq!(
+            ticks(Hertz(HSI), Hertz(2 * HSI), u32::MAX),
+            Err(ClockError::PeriodOutOfRange)
+        );
+        assert_eq!(
+            ticks(Hertz(HSI), Hertz(0), u32::MAX),
+            Err(ClockError::PeriodOutOfRange)
+        );
+    }
+
+    #[test]
+    fn check_frequency_reports_the_nearest() {
+        assert_eq!(check_frequency(None, Hertz(HSI)), Ok(()));
+        assert_eq!(check_frequency(Some(HSI), Hertz(HSI)), Ok(()));
+        assert_eq!(
+            check_frequency(Some(7_000_000), Hertz(6_000_000)),
+            Err(ClockError::FrequencyUnreachable {
+                requested: Hertz(7_000_000),
+                nearest: Hertz(6_000_000),
+            })
+        );
+    }
+}
@@ -4,12 +4,13 @@ use embedded_hal::timer::{Cancel, CountDown, Periodic};
 use void::Void;
 
 use crate::delay::Delay;
-use crate::syscon::{ClockEnable, Clocks, Syscon};
+use crate::syscon::{ticks, ClockEnable, ClockError, Clocks, Syscon};
 use crate::time::Hertz;
 pub(crate) type TimerRegisterBlock = swm050::tmrse0::RegisterBlock;
 
 pub struct Timer<TIMER> {
     clocks: Clocks,
+    timeout: Hertz,
     pub(crate) timer: TIMER,
 }
 
@@ -23,21 +24,57 @@ where
     {
         TIMER::enable(syscon);
         timer.intctrl.write(|w| w.ena().set_bit());
+        let timeout = timeout.into();
         // pause
         let mut timer = Timer {
             timer: timer,
             clocks: syscon.clocks,
+            timeout,
         };
         timer.start(timeout);
 
         timer
     }
 
-    pub fn release(self) -> TIMER {
+    /// Recalculates the timeout after the clocks were changed with `Syscon::reconfigure`
+    ///
+    /// This restarts the current period. Fails if the timeout is too short for the new clocks,
+    /// in which case nothing changes.
+    pub fn refresh(&mut self, clocks: Clocks) -> Result<(), ClockError> {
+        reload_ticks(&clocks, self.timeout)?;
+        self.clocks = clocks;
+        self.start(self.timeout);
+        Ok(())
+    }
+
+    /// Stops the timer and gates its clock
+    pub fn release(self, syscon: &mut Syscon) -> TIMER {
+        reset(&self.timer);
+        TIMER::disable(syscon);
         self.timer
     }
 }
 
+/// Returns the reload value of the timer for periods of `timeout`
+fn reload_ticks(clocks: &Clocks, timeout: Hertz) -> Result<u32, ClockError> {
+    // TODO If the ticks aren't halved, the periods are twice as long as they should be
+    // But it works in delay?
+    match ticks(clocks.timsclk(), timeout, u32::MAX)? / 2 {
+        0 => Err(ClockError::PeriodOutOfRange),
+        ticks => Ok(ticks),
+    }
+}
+
+/// Stops the timer and puts its registers back into their reset state
+pub(crate) fn reset(timer: &TimerRegisterBlock) {
+    timer.ctrl.reset();
+    timer.intctrl.reset();
+    timer.tarval.reset();
+    timer.curval.reset();
+    // Clear overflow flag
+    timer.intoflag.write(|w| unsafe { w.bits(0) });
+}
+
 impl<TIMER> CountDown for Timer<TIMER>
 where
     TIMER: Deref<Target = TimerRegisterBlock>,
@@ -45,6 +82,8 @@ where
     type Time = Hertz;
 
     /// Start the timer with a `timeout`
+    ///
+    /// Panics if the timeout is shorter than two ticks of the timer clock
     fn start<T>(&mut self, timeout: T)
     where
         T: Into<Hertz>,
@@ -55,10 +94,8 @@ where
         // Clear overflow flag
         self.timer.intoflag.write(|w| unsafe { w.bits(0) });
 
-        let frequency = timeout.into().0;
-        // TODO If the ticks aren't halved, the periods are twice as long as they should be
-        // But it works in delay?
-        let ticks = self.clocks.timsclk().0 / frequency / 2;
+        self.timeout = timeout.into();
+        let ticks = reload_ticks(&self.clocks, self.timeout).unwrap();
 
         self.timer.tarval.write(|w| unsafe { w.bits(ticks) });
         self.timer.curval.write(|w| unsafe { w.bits(0) });
@@ -1,11 +1,12 @@
 use crate::swm050::WDT;
-use crate::syscon::{ClockEnable, Clocks, Syscon};
+use crate::syscon::{ticks, ClockEnable, ClockError, Clocks, Syscon};
 use crate::time::Hertz;
 use embedded_hal::watchdog;
 
 /// Watchdog instance
 pub struct Watchdog {
     clocks: Clocks,
+    period: Option<Hertz>,
     wdt: WDT,
 }
 
@@ -15,39 +16,74 @@ impl Watchdog {
         Self {
             wdt,
             clocks: syscon.clocks,
+            period: None,
         }
     }
-}
 
-impl watchdog::Watchdog for Watchdog {
-    /// Feed the watchdog, so that at least one `period` goes by before the next
-    /// reset
-    fn feed(&mut self) {
-        self.wdt.crr.write(|w| w.crr().reset());
+    /// Gates the clock of a watchdog which was never started
+    ///
+    /// A started watchdog can't be stopped anymore, so its clock keeps running in that case.
+    pub fn release(self, syscon: &mut Syscon) -> WDT {
+        if self.period.is_none() {
+            WDT::disable(syscon);
+        }
+        self.wdt
     }
-}
 
-impl watchdog::WatchdogEnable for Watchdog {
-    type Time = Hertz;
-    fn start<T>(&mut self, period: T)
+    /// Recalculates the timeout after the clocks were changed with `Syscon::reconfigure`
+    ///
+    /// Fails if the period of a started watchdog can't be reached with the new clocks, in
+    /// which case the old timeout stays active.
+    pub fn refresh(&mut self, clocks: Clocks) -> Result<(), ClockError> {
+        let old = self.clocks;
+        self.clocks = clocks;
+        if let Some(period) = self.period {
+            self.try_start(period).inspect_err(|_| self.clocks = old)?;
+        }
+        Ok(())
+    }
+
+    /// Starts the watchdog, failing if the period is longer than the watchdog supports
+    pub fn try_start<T>(&mut self, period: T) -> Result<(), ClockError>
     where
         T: Into<Hertz>,
     {
-        let time = period.into();
         // TODO Verify function
         // As far as i understand the data sheet, it's basically like this:
         // time2 is used for mode 0, after the interrupt the counter is set
         // to timer2, otherwise timer1
-        let ticks = self.clocks.sclk().0 / time.0;
+        let period = period.into();
+        let ticks = ticks(self.clocks.sclk(), period, u32::MAX)?;
         let mut timerticks = (ticks >> 16).next_power_of_two();
         let mut timer1 = 0;
         while timerticks != 0 {
             timer1 += 1;
             timerticks >>= 1;
         }
-        // This shouldn't happen, but let's make sure
-        assert!(timer1 < 16);
+        if timer1 >= 16 {
+            return Err(ClockError::PeriodOutOfRange);
+        }
         self.wdt.torr.write(|w| w.top_init().bits(timer1 as u8));
         self.wdt.cr.write(|w| w.en().set_bit());
+        self.period = Some(period);
+        Ok(())
+    }
+}
+
+impl watchdog::Watchdog for Watchdog {
+    /// Feed the watchdog, so that at least one `period` goes by before the next
+    /// reset
+    fn feed(&mut self) {
+        self.wdt.crr.write(|w| w.crr().reset());
+    }
+}
+
+impl watchdog::WatchdogEnable for Watchdog {
+    type Time = Hertz;
+    fn start<T>(&mut self, period: T)
+    where
+        T: Into<Hertz>,
+    {
+        self.try_start(period).unwrap();
     }
 }